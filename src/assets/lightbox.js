@@ -0,0 +1,101 @@
+// mdbook-plantuml lightbox: opens diagrams in an in-page overlay with pan and
+// zoom (mouse wheel / drag, pinch is left to the browser).
+(function () {
+    "use strict";
+
+    var overlay = null;
+    var image = null;
+    var newTabLink = null;
+    var view = { scale: 1, x: 0, y: 0 };
+    var drag = null;
+
+    function applyView() {
+        image.style.transform = "translate(" + view.x + "px, " + view.y + "px) scale(" + view.scale + ")";
+    }
+
+    function close() {
+        overlay.classList.remove("mdbook-plantuml-lightbox-open");
+        image.removeAttribute("src");
+        document.removeEventListener("keydown", onKeyDown);
+    }
+
+    function onKeyDown(event) {
+        if (event.key === "Escape") {
+            close();
+        } else if (event.key === "+" || event.key === "=") {
+            zoom(1.25);
+        } else if (event.key === "-") {
+            zoom(0.8);
+        } else if (event.key === "0") {
+            view = { scale: 1, x: 0, y: 0 };
+            applyView();
+        }
+    }
+
+    function zoom(factor) {
+        view.scale = Math.min(Math.max(view.scale * factor, 0.2), 20);
+        applyView();
+    }
+
+    function createOverlay() {
+        overlay = document.createElement("div");
+        overlay.className = "mdbook-plantuml-lightbox";
+        overlay.innerHTML =
+            '<div class="mdbook-plantuml-lightbox-toolbar">' +
+            '<a class="mdbook-plantuml-lightbox-newtab" target="_blank" rel="noopener">Open in new tab</a>' +
+            '<button type="button" class="mdbook-plantuml-lightbox-close" aria-label="Close">&times;</button>' +
+            "</div>" +
+            '<img class="mdbook-plantuml-lightbox-image" alt="">';
+        document.body.appendChild(overlay);
+
+        image = overlay.querySelector(".mdbook-plantuml-lightbox-image");
+        newTabLink = overlay.querySelector(".mdbook-plantuml-lightbox-newtab");
+        overlay.querySelector(".mdbook-plantuml-lightbox-close").addEventListener("click", close);
+        overlay.addEventListener("click", function (event) {
+            if (event.target === overlay) {
+                close();
+            }
+        });
+        overlay.addEventListener("wheel", function (event) {
+            event.preventDefault();
+            zoom(event.deltaY < 0 ? 1.1 : 1 / 1.1);
+        }, { passive: false });
+        image.addEventListener("mousedown", function (event) {
+            event.preventDefault();
+            drag = { x: event.clientX - view.x, y: event.clientY - view.y };
+        });
+        document.addEventListener("mousemove", function (event) {
+            if (drag) {
+                view.x = event.clientX - drag.x;
+                view.y = event.clientY - drag.y;
+                applyView();
+            }
+        });
+        document.addEventListener("mouseup", function () {
+            drag = null;
+        });
+    }
+
+    function open(src) {
+        if (!overlay) {
+            createOverlay();
+        }
+        view = { scale: 1, x: 0, y: 0 };
+        applyView();
+        image.src = src;
+        newTabLink.href = src;
+        overlay.classList.add("mdbook-plantuml-lightbox-open");
+        document.addEventListener("keydown", onKeyDown);
+    }
+
+    document.addEventListener("click", function (event) {
+        var target = event.target;
+        if (target.tagName !== "IMG" || !target.closest(".content") ||
+            target.getAttribute("src").indexOf("mdbook-plantuml-img/") === -1) {
+            return;
+        }
+
+        event.preventDefault();
+        open(target.currentSrc || target.src);
+    });
+})();
@@ -1,3 +1,4 @@
+use anyhow::{format_err, Result};
 use base64::{
     alphabet::Alphabet,
     engine::fast_portable::{self, FastPortable},
@@ -14,11 +15,32 @@ pub fn encode(data: &[u8]) -> String {
     base64::encode_engine(data, &ENGINE)
 }
 
+/// Decode PlantUML's base64 dialect (with or without padding)
+pub fn decode(text: &str) -> Result<Vec<u8>> {
+    base64::decode_engine(text.trim_end_matches('='), &ENGINE)
+        .map_err(|e| format_err!("Invalid PlantUML encoded data ({})", e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn decodes_encoded_bytes() {
+        assert_eq!(b"froboz".to_vec(), decode("Pd9lOczw").unwrap());
+        let data: Vec<u8> = (0_u8..255_u8).collect();
+        assert_eq!(data, decode(&encode(&data)).unwrap());
+        // Without padding, as used by PlantUML itself
+        assert_eq!(b"ab".to_vec(), decode(&encode(b"ab")).unwrap());
+        assert_eq!(
+            b"ab".to_vec(),
+            decode(encode(b"ab").trim_end_matches('=')).unwrap()
+        );
+
+        assert!(decode("Pd9l+czw").is_err());
+    }
+
     #[test]
     fn encodes_bytes() {
         assert_eq!(String::from("Pd9lOczw"), encode(b"froboz"));
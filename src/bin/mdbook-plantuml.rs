@@ -1,64 +1,268 @@
 use clap::{Arg, ArgMatches, Command};
+use log::LevelFilter;
 use mdbook::errors::Error as MDBookError;
 use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
 use mdbook_plantuml::PlantUMLPreprocessor;
 use std::error::Error;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
 
 pub fn make_app() -> Command<'static> {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-    Command::new("mdBook PlantUML preprocessor")
+    let app = Command::new("mdBook PlantUML preprocessor")
         .version(VERSION)
         .author("Sytse Reitsma")
         .about("An mdbook preprocessor which renders PlantUML code blocks to SVG diagrams")
+        .arg(Arg::new("log").short('l').help(
+            "Log to './output.log' at debug level (may help troubleshooting rendering \
+                     issues), same as --log-file output.log --log-level debug.",
+        ))
+        .arg(Arg::new("strict-version").long("strict-version").help(
+            "Fail instead of warning when called from an older mdbook version than the one \
+             this preprocessor was built against.",
+        ))
         .arg(
-            Arg::new("log")
-                .short('l')
-                .help("Log to './output.log' (may help troubleshooting rendering issues)."),
+            Arg::new("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .global(true)
+                .help("Log to the given file (warnings and errors are logged to stderr too)."),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .takes_value(true)
+                .global(true)
+                .possible_values(["off", "error", "warn", "info", "debug", "trace"])
+                .help(
+                    "The log level (warn by default), overrides the MDBOOK_LOG and RUST_LOG \
+                     environment variables and the log-level option in book.toml.",
+                ),
         )
         .subcommand(
             Command::new("supports")
                 .arg(Arg::new("renderer").required(true))
                 .about("Check whether a renderer is supported by this preprocessor"),
         )
+        .subcommand(
+            Command::new("render")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("The markdown (.md) or PlantUML (e.g. .puml) file to render"),
+                )
+                .arg(
+                    Arg::new("output-dir")
+                        .short('o')
+                        .long("output-dir")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("The directory to write the rendered files to"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .takes_value(true)
+                        .help("The image format for PlantUML files (svg by default)"),
+                )
+                .arg(
+                    Arg::new("book-dir")
+                        .short('b')
+                        .long("book-dir")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("The root dir of the book whose book.toml configuration to use"),
+                )
+                .about("Render a single file outside of mdbook (for previewing diagrams)"),
+        )
+        .subcommand(
+            Command::new("check")
+                .arg(
+                    Arg::new("book-dir")
+                        .default_value(".")
+                        .help("The root dir of the book to check"),
+                )
+                .about("Check the syntax of all diagrams in a book without building it"),
+        )
+        .subcommand(
+            Command::new("init")
+                .arg(
+                    Arg::new("book-dir")
+                        .default_value(".")
+                        .help("The root dir of the book to add the preprocessor to"),
+                )
+                .about("Add the preprocessor to a book's book.toml, using the PlantUML found"),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .arg(
+                    Arg::new("book-dir")
+                        .default_value(".")
+                        .help("The root dir of the book whose configuration to diagnose"),
+                )
+                .about("Diagnose the PlantUML installation and configuration"),
+        )
+        .subcommand(
+            Command::new("cache")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("stats")
+                        .arg(book_dir_arg())
+                        .about("Show the number and size of the cached images per format"),
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .arg(book_dir_arg())
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Only list the files that would be removed"),
+                        )
+                        .about("Remove the cached images not used by the book"),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .arg(book_dir_arg())
+                        .about("Find corrupt cached images and files not used by the book"),
+                )
+                .subcommand(
+                    Command::new("clear")
+                        .arg(book_dir_arg())
+                        .about("Remove all cached images"),
+                )
+                .about("Inspect and clean up the image cache (the mdbook-plantuml-img dir)"),
+        );
+
+    #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+    let app = app
+        .subcommand(
+            Command::new("encode")
+                .arg(
+                    Arg::new("server")
+                        .long("server")
+                        .takes_value(true)
+                        .help("The PlantUML server URL, prints the diagram's image URL on it"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .default_value("svg")
+                        .help("The image format of the image URL"),
+                )
+                .about("Encode the diagram source read from stdin for PlantUML server URLs"),
+        )
+        .subcommand(
+            Command::new("decode")
+                .arg(
+                    Arg::new("url")
+                        .required(true)
+                        .help("The PlantUML server URL or the encoded diagram to decode"),
+                )
+                .about("Decode the diagram source from a PlantUML server URL"),
+        );
+
+    app
+}
+
+fn book_dir_arg() -> Arg<'static> {
+    Arg::new("book-dir")
+        .default_value(".")
+        .help("The root dir of the book")
 }
 
 fn main() {
     let matches = make_app().get_matches();
 
+    let log_options = LogOptions::new(&matches);
+    let log_handle = match setup_logging(&log_options) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    // The encode and decode subcommands only exist with a server feature
+    #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+    if let Some(result) = handle_url_subcommands(&matches) {
+        if let Err(e) = result {
+            eprintln!("{:#}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     let preprocessor = PlantUMLPreprocessor;
     if let Some(sub_args) = matches.subcommand_matches("supports") {
         handle_supports(&preprocessor, sub_args);
-    } else {
-        if matches.is_present("log") {
-            if let Err(e) = setup_logging() {
-                eprintln!("{}", e);
+    } else if let Some(sub_args) = matches.subcommand_matches("render") {
+        if let Err(e) = handle_render(sub_args) {
+            eprintln!("{:#}", e);
+            process::exit(1);
+        }
+    } else if let Some(sub_args) = matches.subcommand_matches("check") {
+        match handle_check(sub_args) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{:#}", e);
                 process::exit(2);
             }
         }
-        if let Err(e) = handle_preprocessing(&preprocessor) {
-            eprintln!("{}", e);
+    } else if let Some(sub_args) = matches.subcommand_matches("init") {
+        if let Err(e) = handle_init(sub_args) {
+            eprintln!("{:#}", e);
+            process::exit(1);
+        }
+    } else if let Some(sub_args) = matches.subcommand_matches("doctor") {
+        if !handle_doctor(sub_args) {
             process::exit(1);
         }
+    } else if let Some(sub_args) = matches.subcommand_matches("cache") {
+        match handle_cache(sub_args) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                process::exit(2);
+            }
+        }
+    } else if let Err(e) = handle_preprocessing(
+        &preprocessor,
+        matches.is_present("strict-version"),
+        &log_options,
+        &log_handle,
+    ) {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
 
-fn handle_preprocessing(pre: &dyn Preprocessor) -> Result<(), MDBookError> {
+fn handle_preprocessing(
+    pre: &dyn Preprocessor,
+    strict_version: bool,
+    log_options: &LogOptions,
+    log_handle: &log4rs::Handle,
+) -> Result<(), MDBookError> {
     let (ctx, book) = CmdPreprocessor::parse_input(io::stdin())?;
 
-    if ctx.mdbook_version != mdbook::MDBOOK_VERSION {
-        // We should probably use the `semver` crate to check compatibility
-        // here...
-        eprintln!(
-            "Warning: The {} plugin was built against version {} of mdbook, but we're being \
-             called from version {}",
-            pre.name(),
-            mdbook::MDBOOK_VERSION,
-            ctx.mdbook_version
-        );
+    // The log level in book.toml is only known now
+    if log_options.level.is_none() {
+        if let Some(level) = mdbook_plantuml::get_config_log_level(&ctx.config) {
+            let config = create_log_config(log_options, Some(level))
+                .map_err(|e| MDBookError::msg(e.to_string()))?;
+            log_handle.set_config(config);
+        }
     }
+
+    mdbook_plantuml::check_mdbook_version(&ctx.mdbook_version, strict_version)
+        .map_err(|e| MDBookError::msg(format!("{:#}", e)))?;
     let processed_book = pre.run(&ctx, book)?;
     serde_json::to_writer(io::stdout(), &processed_book)?;
     Ok(())
@@ -76,26 +280,294 @@ fn handle_supports(pre: &dyn Preprocessor, sub_args: &ArgMatches) -> ! {
     }
 }
 
-fn setup_logging() -> Result<(), Box<dyn Error>> {
-    use log::LevelFilter;
+fn handle_render(sub_args: &ArgMatches) -> anyhow::Result<()> {
+    let file = sub_args.value_of("file").expect("Required argument");
+    let output_dir = sub_args.value_of("output-dir").expect("Has default value");
+    let book_dir = sub_args.value_of("book-dir").expect("Has default value");
+
+    let rendered_files = mdbook_plantuml::render_file(
+        Path::new(book_dir),
+        Path::new(file),
+        Path::new(output_dir),
+        sub_args.value_of("format"),
+    )?;
+    for rendered_file in rendered_files {
+        println!("{}", rendered_file.display());
+    }
+
+    Ok(())
+}
+
+/// Returns false if any of the diagrams has errors
+fn handle_check(sub_args: &ArgMatches) -> anyhow::Result<bool> {
+    let book_dir = sub_args.value_of("book-dir").expect("Has default value");
+
+    let result = mdbook_plantuml::check_book(Path::new(book_dir))?;
+    for diagnostic in &result.diagnostics {
+        println!("{}", diagnostic);
+    }
+    eprintln!(
+        "Checked {} diagrams, {} with errors.",
+        result.diagram_count,
+        result.diagnostics.len()
+    );
+
+    Ok(result.diagnostics.is_empty())
+}
+
+/// Handle the encode and decode subcommands, returns None for other
+/// subcommands
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+fn handle_url_subcommands(matches: &ArgMatches) -> Option<anyhow::Result<()>> {
+    match matches.subcommand() {
+        Some(("encode", sub_args)) => Some(handle_encode(sub_args)),
+        Some(("decode", sub_args)) => Some(handle_decode(sub_args)),
+        _ => None,
+    }
+}
+
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+fn handle_encode(sub_args: &ArgMatches) -> anyhow::Result<()> {
+    use std::io::Read;
+
+    let mut plantuml_code = String::new();
+    io::stdin().read_to_string(&mut plantuml_code)?;
+    let format = sub_args.value_of("format").expect("Has default value");
+
+    println!(
+        "{}",
+        mdbook_plantuml::encode_diagram(&plantuml_code, sub_args.value_of("server"), format)?
+    );
+    Ok(())
+}
+
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+fn handle_decode(sub_args: &ArgMatches) -> anyhow::Result<()> {
+    let url = sub_args.value_of("url").expect("Required argument");
+
+    let plantuml_code = mdbook_plantuml::decode_diagram(url)?;
+    if plantuml_code.ends_with('\n') {
+        print!("{}", plantuml_code);
+    } else {
+        println!("{}", plantuml_code);
+    }
+    Ok(())
+}
+
+fn handle_init(sub_args: &ArgMatches) -> anyhow::Result<()> {
+    let book_dir = sub_args.value_of("book-dir").expect("Has default value");
+
+    let result = mdbook_plantuml::init_book(Path::new(book_dir))?;
+    if !result.added_preprocessor {
+        println!("book.toml already has a [preprocessor.plantuml] table, leaving it as is.");
+    } else if let Some(plantuml_cmd) = &result.plantuml_cmd {
+        println!(
+            "Added [preprocessor.plantuml] to book.toml, using plantuml-cmd = \"{}\".",
+            plantuml_cmd
+        );
+    } else {
+        println!(
+            "Added [preprocessor.plantuml] to book.toml, but found no PlantUML install or \
+             server. Please set plantuml-cmd in book.toml."
+        );
+    }
+    if let Some(entry) = &result.gitignore_entry {
+        println!("Added {} to .gitignore.", entry);
+    }
+
+    Ok(())
+}
+
+fn handle_doctor(sub_args: &ArgMatches) -> bool {
+    let book_dir = sub_args.value_of("book-dir").expect("Has default value");
+
+    let findings = mdbook_plantuml::diagnose(Path::new(book_dir));
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    !findings
+        .iter()
+        .any(|finding| finding.status == mdbook_plantuml::Status::Error)
+}
+
+/// Returns false if verifying the cache found problems
+fn handle_cache(sub_args: &ArgMatches) -> anyhow::Result<bool> {
+    use mdbook_plantuml::format_size;
+
+    let (command, command_args) = sub_args.subcommand().expect("Subcommand is required");
+    let book_dir = Path::new(
+        command_args
+            .value_of("book-dir")
+            .expect("Has default value"),
+    );
+    let report_missing = |missing_images| {
+        if missing_images > 0 {
+            println!(
+                "{} images used by the book are not cached, the next build renders them.",
+                missing_images
+            );
+        }
+    };
+
+    match command {
+        "stats" => println!("{}", mdbook_plantuml::get_cache_stats(book_dir)?),
+        "prune" => {
+            let dry_run = command_args.is_present("dry-run");
+            let result = mdbook_plantuml::prune_cache(book_dir, dry_run)?;
+            let action = if dry_run { "Would remove" } else { "Removed" };
+            for file in &result.removed {
+                println!("{} {}", action, file.display());
+            }
+            println!(
+                "{} {} files ({}).",
+                action,
+                result.removed.len(),
+                format_size(result.removed_size)
+            );
+            report_missing(result.missing_images);
+        }
+        "verify" => {
+            let result = mdbook_plantuml::verify_cache(book_dir)?;
+            for file in &result.corrupt {
+                println!("Corrupt: {}", file.display());
+            }
+            for file in &result.orphaned {
+                println!("Orphaned: {}", file.display());
+            }
+            println!(
+                "Verified {} cached images, {} corrupt, {} orphaned files.",
+                result.checked,
+                result.corrupt.len(),
+                result.orphaned.len()
+            );
+            report_missing(result.missing_images);
+            if !result.corrupt.is_empty() {
+                println!("Remove the corrupt images to render them again.");
+            }
+            if !result.orphaned.is_empty() {
+                println!("Run `mdbook-plantuml cache prune` to remove the orphaned files.");
+            }
+            return Ok(result.corrupt.is_empty() && result.orphaned.is_empty());
+        }
+        "clear" => {
+            let removed = mdbook_plantuml::clear_cache(book_dir)?;
+            println!("Removed {} files.", removed);
+        }
+        _ => unreachable!("Unknown cache subcommand {}", command),
+    }
+
+    Ok(true)
+}
+
+/// The logging configuration from the command line and environment
+struct LogOptions {
+    /// The file to log to (in addition to stderr)
+    file: Option<PathBuf>,
+    /// The log level set on the command line or in the environment, this takes
+    /// precedence over the log level in book.toml
+    level: Option<LevelFilter>,
+    /// The log level used when no log level is set
+    default_level: LevelFilter,
+}
+
+impl LogOptions {
+    fn new(matches: &ArgMatches) -> Self {
+        let log_to_output_file = matches.is_present("log");
+        let file = matches
+            .value_of("log-file")
+            .map(PathBuf::from)
+            .or_else(|| log_to_output_file.then(|| PathBuf::from("output.log")));
+        let level = matches
+            .value_of("log-level")
+            .and_then(|level| LevelFilter::from_str(level).ok())
+            .or_else(mdbook_plantuml::get_env_log_level);
+        let default_level = if log_to_output_file {
+            LevelFilter::Debug
+        } else {
+            LevelFilter::Warn
+        };
+
+        Self {
+            file,
+            level,
+            default_level,
+        }
+    }
+}
+
+/// Appender clearing the rendering progress line on stderr before writing a
+/// log record, so the record does not end up on the progress line
+#[derive(Debug)]
+struct ProgressAwareAppender<A>(A);
+
+impl<A: log4rs::append::Append> log4rs::append::Append for ProgressAwareAppender<A> {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        mdbook_plantuml::clear_progress_line();
+        self.0.append(record)
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Create the log4rs configuration, `config_level` is the log level from
+/// book.toml (if any).
+fn create_log_config(
+    options: &LogOptions,
+    config_level: Option<LevelFilter>,
+) -> Result<log4rs::Config, Box<dyn Error>> {
+    use log4rs::append::console::{ConsoleAppender, Target};
     use log4rs::append::file::FileAppender;
-    use log4rs::config::{Appender, Config, Root};
+    use log4rs::config::{Appender, Config, Logger, Root};
     use log4rs::encode::pattern::PatternEncoder;
+    use log4rs::filter::threshold::ThresholdFilter;
 
-    let logfile = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{l} - {m}\n")))
-        .build("output.log")?;
+    let level = options
+        .level
+        .or(config_level)
+        .unwrap_or(options.default_level);
 
-    let config = Config::builder()
-        .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .build(
-            Root::builder()
-                .appender("logfile")
-                .build(LevelFilter::Debug),
-        )?;
-    log4rs::init_config(config)?;
+    // Same format as mdbook's own log output
+    let stderr = ConsoleAppender::builder()
+        .target(Target::Stderr)
+        .encoder(Box::new(PatternEncoder::new(
+            "{d(%Y-%m-%d %H:%M:%S)} [{l}] \\({t}\\): {m}{n}",
+        )))
+        .build();
+    // The log file gets everything, stderr only the warnings and errors
+    let stderr_level = if options.file.is_some() {
+        level.min(LevelFilter::Warn)
+    } else {
+        level
+    };
+    let mut config = Config::builder().appender(
+        Appender::builder()
+            .filter(Box::new(ThresholdFilter::new(stderr_level)))
+            .build("stderr", Box::new(ProgressAwareAppender(stderr))),
+    );
+    let mut root = Root::builder().appender("stderr");
+
+    if let Some(file) = &options.file {
+        let logfile = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{l} - {m}\n")))
+            .build(file)?;
+        config = config.appender(Appender::builder().build("logfile", Box::new(logfile)));
+        root = root.appender("logfile");
+    }
+
+    // The level applies to the preprocessor, other crates (e.g. mdbook) only
+    // log warnings and errors
+    Ok(config
+        .logger(Logger::builder().build("mdbook_plantuml", level))
+        .build(root.build(level.min(LevelFilter::Warn)))?)
+}
+
+fn setup_logging(options: &LogOptions) -> Result<log4rs::Handle, Box<dyn Error>> {
+    let handle = log4rs::init_config(create_log_config(options, None)?)?;
 
     log::info!("--- Started preprocessor ---");
 
-    Ok(())
+    Ok(handle)
 }
@@ -0,0 +1,204 @@
+use crate::markdown_plantuml_pipeline::get_diagrams;
+use crate::plantuml_backend::PlantUMLBackend;
+use crate::plantuml_backend_factory;
+use crate::{get_plantuml_config, load_book};
+use anyhow::{Context, Result};
+use mdbook::book::BookItem;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A problem found in one of the book's diagrams
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The source file of the chapter containing the diagram
+    pub file: PathBuf,
+    /// The (one based) line of the problem in the source file
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: error: {}",
+            self.file.display(),
+            self.line,
+            self.message
+        )
+    }
+}
+
+/// The result of checking all the diagrams in a book
+pub struct CheckResult {
+    /// The number of diagrams checked
+    pub diagram_count: usize,
+    /// The problems found, in book order
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Check the syntax of all the PlantUML diagrams in the book in `book_root`
+/// without rendering them, using the backend configured in its book.toml.
+/// Returns an error when a diagram could not be checked (e.g. when the
+/// PlantUML server is unreachable).
+pub fn check_book(book_root: &Path) -> Result<CheckResult> {
+    let book = load_book(book_root)?;
+    let cfg = get_plantuml_config(&book.config);
+
+    let backend = plantuml_backend_factory::create(&cfg);
+    let src_dir = book_root.join(&book.config.book.src);
+    let mut result = CheckResult {
+        diagram_count: 0,
+        diagnostics: Vec::new(),
+    };
+    for item in book.iter() {
+        if let BookItem::Chapter(chapter) = item {
+            if let Some(source_path) = &chapter.source_path {
+                check_chapter(
+                    backend.as_ref(),
+                    &src_dir.join(source_path),
+                    &chapter.content,
+                    &mut result,
+                )?;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn check_chapter(
+    backend: &dyn PlantUMLBackend,
+    file: &Path,
+    markdown: &str,
+    result: &mut CheckResult,
+) -> Result<()> {
+    for diagram in get_diagrams(markdown) {
+        result.diagram_count += 1;
+        let error = backend.check_syntax(&diagram.code).with_context(|| {
+            format!(
+                "Failed to check the diagram at {}:{}",
+                file.display(),
+                diagram.line
+            )
+        })?;
+        let (line, message) = match error {
+            Some(error) => (diagram.line + error.line.unwrap_or(0), error.message),
+            None => continue,
+        };
+        log::debug!("{}:{}: {}", file.display(), line, message);
+        result.diagnostics.push(Diagnostic {
+            file: file.to_path_buf(),
+            line,
+            message,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plantuml_backend::SyntaxError;
+    use anyhow::bail;
+    use pretty_assertions::assert_eq;
+
+    /// Backend reporting an error on the second line of diagrams containing
+    /// `error`, and failing for diagrams containing `fail`
+    struct CheckingBackendMock;
+
+    impl PlantUMLBackend for CheckingBackendMock {
+        fn render_from_string(
+            &self,
+            _plantuml_code: &str,
+            _image_format: &str,
+            _output_file: &Path,
+        ) -> Result<()> {
+            bail!("Not expected to render")
+        }
+
+        fn check_syntax(&self, plantuml_code: &str) -> Result<Option<SyntaxError>> {
+            if plantuml_code.contains("fail") {
+                bail!("Server unreachable")
+            } else if plantuml_code.contains("error") {
+                Ok(Some(SyntaxError {
+                    line: Some(1),
+                    message: String::from("Syntax Error?"),
+                }))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+
+    #[test]
+    fn reports_the_line_of_errors_in_the_chapter() {
+        let markdown = "# Chapter\n\n\
+                        ```plantuml\n@startuml\nA --> B\n@enduml\n```\n\n\
+                        ```plantuml\n@startuml\nerror\n@enduml\n```\n";
+        let mut result = CheckResult {
+            diagram_count: 0,
+            diagnostics: Vec::new(),
+        };
+        check_chapter(
+            &CheckingBackendMock {},
+            Path::new("src/chapter.md"),
+            markdown,
+            &mut result,
+        )
+        .unwrap();
+
+        assert_eq!(2, result.diagram_count);
+        let diagnostics: Vec<String> = result.diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(vec!["src/chapter.md:11: error: Syntax Error?"], diagnostics);
+    }
+
+    #[test]
+    fn fails_when_a_diagram_cannot_be_checked() {
+        let markdown = "# Chapter\n\n```puml\nfail\n```\n";
+        let mut result = CheckResult {
+            diagram_count: 0,
+            diagnostics: Vec::new(),
+        };
+        let error = check_chapter(
+            &CheckingBackendMock {},
+            Path::new("src/chapter.md"),
+            markdown,
+            &mut result,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            "Failed to check the diagram at src/chapter.md:4: Server unreachable",
+            format!("{:#}", error)
+        );
+        assert!(result.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn checks_the_chapters_in_the_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let src_dir = dir.path().join("src");
+        std::fs::create_dir(&src_dir).unwrap();
+        std::fs::write(
+            src_dir.join("SUMMARY.md"),
+            "# Summary\n\n- [Intro](intro.md)\n",
+        )
+        .unwrap();
+        std::fs::write(src_dir.join("intro.md"), "# Intro\n\nNo diagrams.\n").unwrap();
+
+        let result = check_book(dir.path()).unwrap();
+        assert_eq!(0, result.diagram_count);
+        assert!(result.diagnostics.is_empty());
+
+        // Missing chapters are an error, and are not created
+        std::fs::write(
+            src_dir.join("SUMMARY.md"),
+            "# Summary\n\n- [Intro](intro.md)\n- [Missing](missing.md)\n",
+        )
+        .unwrap();
+        assert!(check_book(dir.path()).is_err());
+        assert!(!src_dir.join("missing.md").exists());
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The render statistics of a single diagram
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiagramRecord {
+    /// The chapter containing the diagram (relative to the book's src dir)
+    pub chapter: Option<PathBuf>,
+    /// The (one based) line of the code block in the chapter
+    pub line: usize,
+    /// The hash of the diagram source (the base name of its image files)
+    pub hash: String,
+    /// The image format (e.g. `svg`)
+    pub format: String,
+    /// The backend used for rendering (`shell` or `server`)
+    pub backend: &'static str,
+    /// True if the image was cached already (i.e. was not rendered)
+    pub cache_hit: bool,
+    /// Time spent on the diagram, in milliseconds
+    pub duration_ms: f64,
+    /// The total size of the diagram's image pages in bytes
+    pub output_size: u64,
+    /// The rendering error, if any
+    pub error: Option<String>,
+}
+
+impl DiagramRecord {
+    pub fn new(line: usize, hash: &str, format: &str) -> Self {
+        Self {
+            chapter: None,
+            line,
+            hash: String::from(hash),
+            format: String::from(format),
+            backend: "",
+            cache_hit: false,
+            duration_ms: 0.0,
+            output_size: 0,
+            error: None,
+        }
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration_ms = duration.as_secs_f64() * 1000.0;
+    }
+}
+
+/// The totals of all the diagrams in the report
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub diagrams: usize,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    pub errors: usize,
+    pub duration_ms: f64,
+    pub output_size: u64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    diagrams: &'a [DiagramRecord],
+    totals: Totals,
+}
+
+/// Collects the render statistics of the diagrams in a book build
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    backend: &'static str,
+    chapter: Option<PathBuf>,
+    diagrams: Vec<DiagramRecord>,
+}
+
+impl BuildReport {
+    /// Create a report for a build using the given backend
+    pub fn new(backend: &'static str) -> Self {
+        Self {
+            backend,
+            ..Self::default()
+        }
+    }
+
+    /// Start a new chapter, diagrams added after this are part of it
+    pub fn start_chapter(&mut self, chapter_path: &Path) {
+        self.chapter = Some(chapter_path.to_path_buf());
+    }
+
+    /// Add a diagram of the current chapter
+    pub fn add_diagram(&mut self, mut record: DiagramRecord) {
+        record.chapter = self.chapter.clone();
+        record.backend = self.backend;
+        self.diagrams.push(record);
+    }
+
+    pub fn get_totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for diagram in &self.diagrams {
+            totals.diagrams += 1;
+            if diagram.error.is_some() {
+                totals.errors += 1;
+            } else if diagram.cache_hit {
+                totals.cache_hits += 1;
+            } else {
+                totals.cache_misses += 1;
+            }
+            totals.duration_ms += diagram.duration_ms;
+            totals.output_size += diagram.output_size;
+        }
+
+        totals
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let report = Report {
+            diagrams: &self.diagrams,
+            totals: self.get_totals(),
+        };
+        Ok(serde_json::to_string_pretty(&report)?)
+    }
+
+    /// Write the report as JSON to `report_file`, creating its dir if needed
+    pub fn write(&self, report_file: &Path) -> Result<()> {
+        if let Some(dir) = report_file.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create the report dir {:?}", dir))?;
+        }
+        fs::write(report_file, self.to_json()?)
+            .with_context(|| format!("Failed to write the build report {:?}", report_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_totals() {
+        let mut report = BuildReport::new("shell");
+        report.start_chapter(Path::new("intro.md"));
+        let mut cached = DiagramRecord::new(3, "abc", "svg");
+        cached.cache_hit = true;
+        cached.output_size = 100;
+        cached.set_duration(Duration::from_millis(2));
+        report.add_diagram(cached);
+
+        report.start_chapter(Path::new("design/storage.md"));
+        let mut rendered = DiagramRecord::new(10, "def", "png");
+        rendered.output_size = 2000;
+        rendered.set_duration(Duration::from_millis(500));
+        report.add_diagram(rendered);
+        let mut failed = DiagramRecord::new(20, "123", "svg");
+        failed.error = Some(String::from("Syntax Error?"));
+        report.add_diagram(failed);
+
+        assert_eq!(
+            Totals {
+                diagrams: 3,
+                cache_hits: 1,
+                cache_misses: 1,
+                errors: 1,
+                duration_ms: 502.0,
+                output_size: 2100,
+            },
+            report.get_totals()
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!("intro.md", json["diagrams"][0]["chapter"]);
+        assert_eq!(true, json["diagrams"][0]["cache_hit"]);
+        assert_eq!("shell", json["diagrams"][0]["backend"]);
+        assert_eq!("design/storage.md", json["diagrams"][2]["chapter"]);
+        assert_eq!(20, json["diagrams"][2]["line"]);
+        assert_eq!("Syntax Error?", json["diagrams"][2]["error"]);
+        assert_eq!(3, json["totals"]["diagrams"]);
+    }
+}
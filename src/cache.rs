@@ -0,0 +1,428 @@
+use crate::image_size::PNG_SIGNATURE;
+use crate::plantuml_backend::{PlantUMLBackend, SyntaxError};
+use crate::plantuml_renderer::PlantUMLRenderer;
+use crate::plantumlconfig::ZoomMode;
+use crate::{get_img_output_dir, get_plantuml_config, load_book, load_book_config, render_book};
+use anyhow::{bail, Context, Result};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const PNG_END: &[u8] = b"IEND\xaeB`\x82";
+
+/// The contents of the image cache (i.e. the image dir)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub img_dir: PathBuf,
+    pub file_count: usize,
+    /// Total size in bytes
+    pub total_size: u64,
+    /// The file count and size per format (i.e. file extension)
+    pub formats: BTreeMap<String, (usize, u64)>,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Image dir: {}", self.img_dir.display())?;
+        write!(
+            f,
+            "Files: {} ({})",
+            self.file_count,
+            format_size(self.total_size)
+        )?;
+        for (format, (count, size)) in &self.formats {
+            write!(f, "\n  {}: {} ({})", format, count, format_size(*size))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The outcome of pruning the cache
+pub struct PruneResult {
+    /// The removed files (the files that would be removed for a dry run)
+    pub removed: Vec<PathBuf>,
+    /// Total size of the removed files in bytes
+    pub removed_size: u64,
+    /// The number of images used by the book that are not cached
+    pub missing_images: usize,
+}
+
+/// The outcome of verifying the cache
+pub struct VerifyResult {
+    /// The number of cached images used by the book
+    pub checked: usize,
+    /// Cached images used by the book that are not valid images (e.g. empty or
+    /// truncated files)
+    pub corrupt: Vec<PathBuf>,
+    /// Files not used by the book (these are removed by the next build)
+    pub orphaned: Vec<PathBuf>,
+    /// The number of images used by the book that are not cached
+    pub missing_images: usize,
+}
+
+/// Format a file size in bytes for humans
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Get the file count and sizes of the image cache of the book in `book_root`
+pub fn get_cache_stats(book_root: &Path) -> Result<CacheStats> {
+    let img_dir = get_cache_dir(book_root)?;
+    let mut stats = CacheStats {
+        file_count: 0,
+        total_size: 0,
+        formats: BTreeMap::new(),
+        img_dir: img_dir.clone(),
+    };
+    for file in list_files(&img_dir)? {
+        let size = get_file_size(&file);
+        stats.file_count += 1;
+        stats.total_size += size;
+        let format = stats.formats.entry(get_format(&file)).or_insert((0, 0));
+        format.0 += 1;
+        format.1 += size;
+    }
+
+    Ok(stats)
+}
+
+/// Remove the cached images not used by the book in `book_root` (the files
+/// the next build would remove), without rendering anything.
+pub fn prune_cache(book_root: &Path, dry_run: bool) -> Result<PruneResult> {
+    let usage = get_cache_usage(book_root)?;
+    let removed_size = usage.obsolete.iter().map(|f| get_file_size(f)).sum();
+    if !dry_run {
+        remove_files(&usage.obsolete)?;
+    }
+
+    Ok(PruneResult {
+        removed: usage.obsolete,
+        removed_size,
+        missing_images: usage.missing_images,
+    })
+}
+
+/// Check the cached images used by the book in `book_root` (i.e. the files
+/// named after the hash of the book's diagram sources) are valid images, and
+/// find the files that are not used by the book.
+pub fn verify_cache(book_root: &Path) -> Result<VerifyResult> {
+    let usage = get_cache_usage(book_root)?;
+    let corrupt = usage
+        .used
+        .iter()
+        .filter(|file| !is_valid_image(file))
+        .cloned()
+        .collect();
+
+    Ok(VerifyResult {
+        checked: usage.used.len(),
+        corrupt,
+        orphaned: usage.obsolete,
+        missing_images: usage.missing_images,
+    })
+}
+
+/// Remove all cached images of the book in `book_root`. Returns the number of
+/// removed files.
+pub fn clear_cache(book_root: &Path) -> Result<usize> {
+    let files = list_files(&get_cache_dir(book_root)?)?;
+    remove_files(&files)?;
+
+    Ok(files.len())
+}
+
+/// Backend that does not render anything, so rendering the book only uses
+/// (and keeps) the cached images. Counts the images that are not cached.
+struct CacheOnlyBackend {
+    missing_images: Rc<Cell<usize>>,
+}
+
+impl PlantUMLBackend for CacheOnlyBackend {
+    fn render_from_string(
+        &self,
+        _plantuml_code: &str,
+        _image_format: &str,
+        _output_file: &Path,
+    ) -> Result<()> {
+        self.missing_images.set(self.missing_images.get() + 1);
+        bail!("Not cached")
+    }
+
+    fn check_syntax(&self, _plantuml_code: &str) -> Result<Option<SyntaxError>> {
+        Ok(None)
+    }
+}
+
+/// The cached images used by the book, and the files that are not
+struct CacheUsage {
+    used: Vec<PathBuf>,
+    obsolete: Vec<PathBuf>,
+    missing_images: usize,
+}
+
+/// Find the files in the cache used by the book, by rendering the book like a
+/// build does (so the same files are kept), using the cached images only.
+fn get_cache_usage(book_root: &Path) -> Result<CacheUsage> {
+    let img_dir = get_cache_dir(book_root)?;
+    let mut book = load_book(book_root)?;
+    let cfg = get_plantuml_config(&book.config);
+
+    let missing_images = Rc::new(Cell::new(0));
+    let backend = CacheOnlyBackend {
+        missing_images: Rc::clone(&missing_images),
+    };
+    let renderer = PlantUMLRenderer::with_backend(&cfg, &img_dir, Box::new(backend));
+    // Rendering fails for every image that is not cached, which is expected
+    // here. Other errors (e.g. unknown figure references) do not affect the
+    // files used.
+    renderer.disable_failure_logging();
+    if cfg.zoom == Some(ZoomMode::Lightbox) {
+        renderer.keep_lightbox_assets();
+    }
+    render_book(&mut book.book, &cfg, &renderer);
+    let obsolete = renderer.get_obsolete_files();
+    renderer.disable_cleanup();
+
+    let used = list_files(&img_dir)?
+        .into_iter()
+        .filter(|file| !obsolete.contains(file))
+        .collect();
+
+    Ok(CacheUsage {
+        used,
+        obsolete,
+        missing_images: missing_images.get(),
+    })
+}
+
+fn get_cache_dir(book_root: &Path) -> Result<PathBuf> {
+    let config = load_book_config(book_root)?;
+    let img_dir = get_img_output_dir(book_root, &config);
+    if !img_dir.is_dir() {
+        bail!(
+            "The image dir {:?} does not exist, build the book first.",
+            img_dir
+        );
+    }
+
+    Ok(img_dir)
+}
+
+/// List the files (not the sub dirs) in `dir`, sorted by path
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to list {:?}", dir))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+fn remove_files(files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        fs::remove_file(file).with_context(|| format!("Failed to remove {:?}", file))?;
+    }
+
+    Ok(())
+}
+
+fn get_file_size(file: &Path) -> u64 {
+    fs::metadata(file).map_or(0, |metadata| metadata.len())
+}
+
+/// Get the format of a cached file, this is everything after the first dot
+/// of the file name (so braille images are not counted as png images)
+fn get_format(file: &Path) -> String {
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    match file_name.find('.') {
+        Some(dot) => String::from(&file_name[dot + 1..]),
+        None => String::from("(none)"),
+    }
+}
+
+/// Check the contents of an image file match its format, as far as that can be
+/// done cheaply (e.g. svg images must be complete, png images must have the
+/// png header and end chunk)
+fn is_valid_image(file: &Path) -> bool {
+    let content = match fs::read(file) {
+        Ok(content) if !content.is_empty() => content,
+        _ => return false,
+    };
+
+    match file.extension().unwrap_or_default().to_str() {
+        Some("svg") => std::str::from_utf8(&content)
+            .map_or(false, |svg| svg.contains("<svg") && svg.contains("</svg>")),
+        Some("png") => content.starts_with(PNG_SIGNATURE) && content.ends_with(PNG_END),
+        Some("cmapx") => String::from_utf8_lossy(&content).contains("<map"),
+        Some("atxt") | Some("utxt") => std::str::from_utf8(&content).is_ok(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plantuml_renderer::get_image_filename;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    const DIAGRAM: &str = "@startuml\nA --|> B\n@enduml\n";
+
+    /// Create a book with a single chapter containing DIAGRAM, returns the
+    /// image dir
+    fn create_book(book_root: &Path) -> PathBuf {
+        let src_dir = book_root.join("src");
+        let img_dir = src_dir.join("mdbook-plantuml-img");
+        fs::create_dir_all(&img_dir).unwrap();
+        fs::write(src_dir.join("SUMMARY.md"), "# Summary\n\n- [A](a.md)\n").unwrap();
+        fs::write(
+            src_dir.join("a.md"),
+            format!("# A\n\n```plantuml\n{}```\n", DIAGRAM),
+        )
+        .unwrap();
+
+        img_dir
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!("12 B", format_size(12));
+        assert_eq!("1.5 KiB", format_size(1536));
+        assert_eq!("2.0 MiB", format_size(2 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_get_cache_stats() {
+        let dir = tempdir().unwrap();
+        let img_dir = create_book(dir.path());
+        fs::write(img_dir.join("a.svg"), "<svg></svg>").unwrap();
+        fs::write(img_dir.join("b.svg"), "<svg/>").unwrap();
+        fs::write(img_dir.join("c.braille.png"), "x").unwrap();
+        fs::create_dir(img_dir.join("sub")).unwrap();
+
+        let stats = get_cache_stats(dir.path()).unwrap();
+        assert_eq!(3, stats.file_count);
+        assert_eq!(18, stats.total_size);
+        let formats: Vec<(&str, (usize, u64))> = stats
+            .formats
+            .iter()
+            .map(|(format, usage)| (format.as_str(), *usage))
+            .collect();
+        assert_eq!(vec![("braille.png", (1, 1)), ("svg", (2, 17))], formats);
+
+        assert_eq!(3, clear_cache(dir.path()).unwrap());
+        assert_eq!(0, get_cache_stats(dir.path()).unwrap().file_count);
+        assert!(img_dir.join("sub").exists());
+    }
+
+    #[test]
+    fn prunes_the_files_not_used_by_the_book() {
+        let dir = tempdir().unwrap();
+        let img_dir = create_book(dir.path());
+        let used_file = get_image_filename(&img_dir, DIAGRAM, "svg");
+        fs::write(&used_file, "<svg></svg>").unwrap();
+        let obsolete_file = img_dir.join("obsolete.svg");
+        fs::write(&obsolete_file, "<svg></svg>").unwrap();
+
+        let result = prune_cache(dir.path(), true).unwrap();
+        assert_eq!(vec![obsolete_file.clone()], result.removed);
+        assert_eq!(11, result.removed_size);
+        assert!(obsolete_file.exists());
+
+        let result = prune_cache(dir.path(), false).unwrap();
+        assert_eq!(vec![obsolete_file.clone()], result.removed);
+        assert_eq!(0, result.missing_images);
+        assert!(!obsolete_file.exists());
+        assert!(used_file.exists());
+    }
+
+    #[test]
+    fn keeps_the_lightbox_assets() {
+        let dir = tempdir().unwrap();
+        let img_dir = create_book(dir.path());
+        fs::write(
+            dir.path().join("book.toml"),
+            "[preprocessor.plantuml]\nzoom = \"lightbox\"\n",
+        )
+        .unwrap();
+        let css_file = img_dir.join("mdbook-plantuml-lightbox.css");
+        fs::write(&css_file, "").unwrap();
+        let js_file = img_dir.join("mdbook-plantuml-lightbox.js");
+        fs::write(&js_file, "").unwrap();
+        let obsolete_file = img_dir.join("obsolete.svg");
+        fs::write(&obsolete_file, "<svg></svg>").unwrap();
+        let files = vec![css_file, js_file, obsolete_file.clone()];
+
+        let result = prune_cache(dir.path(), true).unwrap();
+        assert_eq!(vec![obsolete_file.clone()], result.removed);
+        assert_eq!(1, result.missing_images);
+        assert_eq!(files, list_files(&img_dir).unwrap());
+
+        // The lightbox assets are used by the book
+        let result = verify_cache(dir.path()).unwrap();
+        assert_eq!(vec![obsolete_file.clone()], result.orphaned);
+        assert_eq!(files, list_files(&img_dir).unwrap());
+
+        let result = prune_cache(dir.path(), false).unwrap();
+        assert_eq!(vec![obsolete_file], result.removed);
+        assert_eq!(files[..2], list_files(&img_dir).unwrap()[..]);
+    }
+
+    #[test]
+    fn verifies_the_files_used_by_the_book() {
+        let dir = tempdir().unwrap();
+        let img_dir = create_book(dir.path());
+        let used_file = get_image_filename(&img_dir, DIAGRAM, "svg");
+        // Truncated image
+        fs::write(&used_file, "<svg><g>").unwrap();
+        let orphaned_file = img_dir.join("orphaned.png");
+        fs::write(&orphaned_file, "").unwrap();
+
+        let result = verify_cache(dir.path()).unwrap();
+        assert_eq!(1, result.checked);
+        assert_eq!(vec![used_file.clone()], result.corrupt);
+        assert_eq!(vec![orphaned_file], result.orphaned);
+
+        fs::remove_file(&used_file).unwrap();
+        let result = verify_cache(dir.path()).unwrap();
+        assert_eq!(0, result.checked);
+        assert_eq!(1, result.missing_images);
+    }
+
+    #[test]
+    fn test_is_valid_image() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let file = dir.path().join(name);
+            fs::write(&file, content).unwrap();
+            file
+        };
+
+        assert!(is_valid_image(&write("a.svg", b"<?xml?><svg><g/></svg>")));
+        assert!(!is_valid_image(&write("b.svg", b"<svg><g/>")));
+        assert!(!is_valid_image(&write("c.eps", b"")));
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(PNG_END);
+        assert!(is_valid_image(&write("d.png", &png)));
+        assert!(!is_valid_image(&write("e.png", PNG_SIGNATURE)));
+    }
+}
@@ -0,0 +1,124 @@
+/// PlantUML keywords that are not worth adding to the search index when
+/// extracting text from the diagram source
+const KEYWORDS: &[&str] = &[
+    "abstract",
+    "activate",
+    "actor",
+    "alt",
+    "as",
+    "boundary",
+    "class",
+    "component",
+    "control",
+    "database",
+    "deactivate",
+    "else",
+    "end",
+    "endif",
+    "entity",
+    "enum",
+    "extends",
+    "if",
+    "implements",
+    "interface",
+    "left",
+    "loop",
+    "node",
+    "note",
+    "of",
+    "opt",
+    "over",
+    "package",
+    "participant",
+    "right",
+    "start",
+    "state",
+    "stop",
+    "then",
+    "title",
+    "usecase",
+];
+
+/// Get the text labels of an SVG diagram (the contents of the `<text>`
+/// elements) in document order, without duplicates. The labels are returned as
+/// found in the SVG, so they are still HTML escaped.
+pub fn extract_svg_text(svg: &str) -> Vec<String> {
+    let mut labels = Vec::new();
+    let mut pos = 0;
+    while let Some(text_start) = svg[pos..].find("<text").map(|p| p + pos) {
+        let content_start = match svg[text_start..].find('>') {
+            Some(end) => text_start + end + 1,
+            None => break,
+        };
+        let content_end = match svg[content_start..].find("</text>") {
+            Some(end) => content_start + end,
+            None => break,
+        };
+
+        add_label(&mut labels, svg[content_start..content_end].trim());
+        pos = content_end;
+    }
+
+    labels
+}
+
+/// Get the words of a diagram's PlantUML source that are likely labels (i.e.
+/// skipping directives, comments and common keywords), without duplicates.
+/// Used for the formats PlantUML does not add the text to (e.g. png).
+pub fn extract_source_text(plantuml_code: &str) -> Vec<String> {
+    let mut labels = Vec::new();
+    for line in plantuml_code.lines().map(str::trim) {
+        if line.starts_with('@')
+            || line.starts_with('!')
+            || line.starts_with('\'')
+            || line.starts_with("skinparam")
+            || line.starts_with("scale")
+        {
+            continue;
+        }
+
+        for word in line.split(|c: char| !c.is_alphanumeric() && c != '_') {
+            if word.chars().count() > 1 && !KEYWORDS.contains(&word.to_lowercase().as_str()) {
+                add_label(&mut labels, word);
+            }
+        }
+    }
+
+    labels
+}
+
+fn add_label(labels: &mut Vec<String>, label: &str) {
+    if !label.is_empty() && !labels.iter().any(|l| l == label) {
+        labels.push(String::from(label));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_extract_svg_text() {
+        let svg = "<svg><g><rect/><text fill=\"#000\" x=\"1\">Storage</text>\
+                   <text x=\"2\">Disk &amp; cache</text><!--comment-->\
+                   <text x=\"3\">Storage</text><text x=\"4\"> </text></g></svg>";
+        assert_eq!(vec!["Storage", "Disk &amp; cache"], extract_svg_text(svg));
+        assert!(extract_svg_text("<svg><text>unterminated</svg>").is_empty());
+    }
+
+    #[test]
+    fn test_extract_source_text() {
+        let code = "@startuml\n\
+                    !theme cyborg\n\
+                    skinparam monochrome true\n\
+                    ' A comment\n\
+                    component \"Storage Engine\" as storage\n\
+                    storage --> [Disk] : writes\n\
+                    @enduml\n";
+        assert_eq!(
+            vec!["Storage", "Engine", "storage", "Disk", "writes"],
+            extract_source_text(code)
+        );
+    }
+}
@@ -43,6 +43,20 @@ impl DirCleaner {
         self.files.remove(img_path);
     }
 
+    /// Get the files that will be removed (i.e. the files not flagged as keep
+    /// so far), sorted by path
+    pub fn get_obsolete_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.files.iter().cloned().collect();
+        files.sort();
+        files
+    }
+
+    /// Keep all the existing files (i.e. disable cleaning)
+    pub fn keep_all(&mut self) {
+        log::info!("DirCleaner - Keeping all files");
+        self.files.clear();
+    }
+
     fn get_files(img_path: &Path) -> HashSet<PathBuf> {
         let mut files = HashSet::new();
         match std::fs::read_dir(img_path) {
@@ -76,7 +90,7 @@ impl DirCleaner {
 impl Drop for DirCleaner {
     fn drop(&mut self) {
         for file in &self.files {
-            if let Err(e) = fs::remove_file(&file) {
+            if let Err(e) = fs::remove_file(file) {
                 log::error!(
                     "DirCleaner - Failed to remove obsolete image file '{}' ({}).",
                     file.to_string_lossy(),
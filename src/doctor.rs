@@ -0,0 +1,468 @@
+use crate::plantuml_backend::PlantUMLBackend;
+use crate::plantuml_backend_factory::{self, BackendKind};
+use crate::plantuml_shell_backend::{create_shell_command, PlantUMLShell};
+use crate::plantumlconfig::PlantUMLConfig;
+use crate::{get_img_output_dir, load_book_config};
+use anyhow::{bail, Result};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::{tempdir, NamedTempFile};
+
+const SAMPLE_DIAGRAM: &str = "@startuml\nAlice -> Bob: Hello\n@enduml\n";
+
+const INSTALL_PLANTUML_FIX: &str = "Install PlantUML (e.g. `apt install plantuml` or `brew \
+     install plantuml`), or set plantuml-cmd in book.toml to your PlantUML command (e.g. \"java \
+     -jar /path/to/plantuml.jar\") or to the URL of a PlantUML server.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The outcome of one of the doctor's checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub status: Status,
+    /// What was checked (e.g. `Graphviz`)
+    pub check: String,
+    /// What was found
+    pub details: String,
+    /// The suggested fix for problems
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &str, details: String) -> Self {
+        Self {
+            status: Status::Ok,
+            check: String::from(check),
+            details,
+            fix: None,
+        }
+    }
+
+    fn problem(status: Status, check: &str, details: String, fix: &str) -> Self {
+        Self {
+            status,
+            check: String::from(check),
+            details,
+            fix: Some(String::from(fix)),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "[ OK ]",
+            Status::Warning => "[WARN]",
+            Status::Error => "[FAIL]",
+        };
+        write!(f, "{} {}: {}", status, self.check, self.details)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n       Fix: {}", fix)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Diagnose the PlantUML toolchain as configured for the book in `book_root`:
+/// the configuration, the PlantUML command (or server) and its dependencies,
+/// the image dir, and rendering a sample diagram.
+pub fn diagnose(book_root: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let cfg = match check_config(book_root, &mut findings) {
+        Some(cfg) => cfg,
+        None => return findings,
+    };
+
+    match plantuml_backend_factory::get_backend_kind(&cfg) {
+        BackendKind::Shell(cmd) => check_shell_backend(book_root, &cmd, &mut findings),
+        #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+        BackendKind::Server(server_url) => check_server_backend(&server_url, &mut findings),
+    }
+
+    findings.push(check_img_dir(book_root));
+    findings.push(check_sample_rendering(book_root, &cfg));
+
+    findings
+}
+
+/// Load the preprocessor configuration, returns None if it is invalid
+fn check_config(book_root: &Path, findings: &mut Vec<Finding>) -> Option<PlantUMLConfig> {
+    const CHECK: &str = "Configuration";
+
+    let config_file = book_root.join("book.toml");
+    if !config_file.exists() {
+        findings.push(Finding::problem(
+            Status::Warning,
+            CHECK,
+            format!(
+                "{:?} not found, using the default configuration",
+                config_file
+            ),
+            "Run mdbook-plantuml from the book's root dir, or pass the book's root dir.",
+        ));
+    }
+
+    let config = match load_book_config(book_root) {
+        Ok(config) => config,
+        Err(e) => {
+            findings.push(Finding::problem(
+                Status::Error,
+                CHECK,
+                format!("{:#}", e),
+                "Fix the syntax error in book.toml.",
+            ));
+            return None;
+        }
+    };
+
+    match config.get("preprocessor.plantuml") {
+        Some(raw) => match raw.clone().try_into::<PlantUMLConfig>() {
+            Ok(cfg) => {
+                findings.push(Finding::ok(
+                    CHECK,
+                    String::from("[preprocessor.plantuml] is valid"),
+                ));
+                Some(cfg)
+            }
+            Err(e) => {
+                findings.push(Finding::problem(
+                    Status::Error,
+                    CHECK,
+                    format!("Invalid [preprocessor.plantuml] configuration ({})", e),
+                    "Fix the option in book.toml (see the Options section of the README).",
+                ));
+                None
+            }
+        },
+        None => {
+            if config_file.exists() {
+                findings.push(Finding::problem(
+                    Status::Warning,
+                    CHECK,
+                    String::from("book.toml has no [preprocessor.plantuml] section"),
+                    "Add a [preprocessor.plantuml] section to book.toml (or run \
+                     `mdbook-plantuml init`), otherwise mdbook does not run the preprocessor.",
+                ));
+            }
+            Some(PlantUMLConfig::default())
+        }
+    }
+}
+
+fn check_shell_backend(book_root: &Path, cmd: &str, findings: &mut Vec<Finding>) {
+    findings.push(Finding::ok(
+        "Backend",
+        format!("PlantUML command `{}`", cmd),
+    ));
+
+    let program = cmd.split_whitespace().next().unwrap_or_default();
+    if is_java(program) {
+        match run_shell(book_root, &format!("{} -version", program)) {
+            Ok(output) => findings.push(Finding::ok("Java", first_line(&output))),
+            Err(e) => {
+                findings.push(Finding::problem(
+                    Status::Error,
+                    "Java",
+                    format!("`{} -version` failed ({})", program, e),
+                    "Install a Java runtime (e.g. OpenJDK) and make sure `java` is on the PATH.",
+                ));
+                return;
+            }
+        }
+
+        match get_jar(cmd) {
+            Some(jar) if book_root.join(jar).is_file() => {
+                findings.push(Finding::ok("PlantUML jar", format!("Found {}", jar)))
+            }
+            Some(jar) => {
+                findings.push(Finding::problem(
+                    Status::Error,
+                    "PlantUML jar",
+                    format!("{} not found (relative to {:?})", jar, book_root),
+                    "Download plantuml.jar from https://plantuml.com/download and set \
+                     plantuml-cmd to \"java -jar /path/to/plantuml.jar\".",
+                ));
+                return;
+            }
+            None => {}
+        }
+    } else if find_program(book_root, program).is_none() {
+        findings.push(Finding::problem(
+            Status::Error,
+            "PlantUML",
+            format!("`{}` not found", program),
+            INSTALL_PLANTUML_FIX,
+        ));
+        return;
+    }
+
+    match run_shell(book_root, &format!("{} -version", cmd)) {
+        Ok(output) => {
+            let version = output
+                .lines()
+                .find(|line| line.contains("PlantUML version"))
+                .map_or_else(|| first_line(&output), String::from);
+            findings.push(Finding::ok("PlantUML", version));
+        }
+        Err(e) => {
+            findings.push(Finding::problem(
+                Status::Error,
+                "PlantUML",
+                format!("`{} -version` failed ({})", cmd, e),
+                INSTALL_PLANTUML_FIX,
+            ));
+            return;
+        }
+    }
+
+    findings.push(check_graphviz(run_shell(
+        book_root,
+        &format!("{} -testdot", cmd),
+    )));
+}
+
+/// Check the output of PlantUML's -testdot option
+fn check_graphviz(testdot_output: Result<String>) -> Finding {
+    const CHECK: &str = "Graphviz";
+    const FIX: &str = "Install Graphviz (https://graphviz.org/download), PlantUML needs it for \
+                       most diagram types other than sequence diagrams. Set the GRAPHVIZ_DOT \
+                       environment variable when dot is not on the PATH.";
+
+    match testdot_output {
+        Ok(output) if output.contains("Installation seems OK") => {
+            let version = output
+                .lines()
+                .find(|line| line.contains("version"))
+                .map_or_else(|| first_line(&output), |line| String::from(line.trim()));
+            Finding::ok(CHECK, version)
+        }
+        Ok(output) => Finding::problem(
+            Status::Warning,
+            CHECK,
+            format!("PlantUML cannot use Graphviz ({})", first_line(&output)),
+            FIX,
+        ),
+        Err(e) => Finding::problem(
+            Status::Warning,
+            CHECK,
+            format!("PlantUML's -testdot failed ({})", e),
+            FIX,
+        ),
+    }
+}
+
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+fn check_server_backend(server_url: &reqwest::Url, findings: &mut Vec<Finding>) {
+    findings.push(Finding::ok(
+        "Backend",
+        format!("PlantUML server {}", server_url),
+    ));
+
+    // Don't hang on an unreachable server
+    let response = reqwest::blocking::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(5))
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .and_then(|client| client.get(server_url.clone()).send());
+    match response {
+        Ok(response) => findings.push(Finding::ok(
+            "PlantUML server",
+            format!("The server answers ({})", response.status()),
+        )),
+        Err(e) => findings.push(Finding::problem(
+            Status::Error,
+            "PlantUML server",
+            format!("The server does not answer ({})", e),
+            "Check the plantuml-cmd URL in book.toml, and that the server is running (e.g. \
+             `docker run -d -p 8080:8080 plantuml/plantuml-server:jetty`).",
+        )),
+    }
+}
+
+/// Check that the image dir (or the source dir it is created in) is writable
+fn check_img_dir(book_root: &Path) -> Finding {
+    const CHECK: &str = "Image dir";
+
+    let config = load_book_config(book_root).unwrap_or_default();
+    let img_dir = get_img_output_dir(book_root, &config);
+    let src_dir = book_root.join(&config.book.src);
+    let dir = if img_dir.exists() { &img_dir } else { &src_dir };
+
+    match NamedTempFile::new_in(dir) {
+        Ok(_) => Finding::ok(CHECK, format!("{:?} is writable", img_dir)),
+        Err(e) => Finding::problem(
+            Status::Error,
+            CHECK,
+            format!("Cannot write to {:?} ({})", dir, e),
+            "Make sure the book's source dir exists and is writable by the current user.",
+        ),
+    }
+}
+
+fn check_sample_rendering(book_root: &Path, cfg: &PlantUMLConfig) -> Finding {
+    const CHECK: &str = "Sample diagram";
+
+    // Render in the book's root dir, like the preprocessor
+    let backend: Box<dyn PlantUMLBackend> = match plantuml_backend_factory::get_backend_kind(cfg) {
+        BackendKind::Shell(cmd) => Box::new(PlantUMLShell::new(cmd).with_working_dir(book_root)),
+        #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+        BackendKind::Server(_) => plantuml_backend_factory::create(cfg),
+    };
+    let result = tempdir().map_err(anyhow::Error::from).and_then(|dir| {
+        let output_file = dir.path().join("sample.svg");
+        backend.render_from_string(SAMPLE_DIAGRAM, "svg", &output_file)?;
+        let size = fs::metadata(&output_file)?.len();
+        if size == 0 {
+            bail!("PlantUML generated an empty image");
+        }
+        Ok(size)
+    });
+
+    match result {
+        Ok(size) => Finding::ok(CHECK, format!("Rendered a sample svg ({} bytes)", size)),
+        Err(e) => Finding::problem(
+            Status::Error,
+            CHECK,
+            format!("Rendering failed ({})", e),
+            "Fix the problems reported above, the details show the PlantUML error.",
+        ),
+    }
+}
+
+fn is_java(program: &str) -> bool {
+    Path::new(program)
+        .file_stem()
+        .map_or(false, |stem| stem == "java")
+}
+
+/// Get the jar file of a `java -jar <jar>` command
+fn get_jar(cmd: &str) -> Option<&str> {
+    cmd.split_whitespace()
+        .skip_while(|word| *word != "-jar")
+        .nth(1)
+}
+
+/// Find a program (relative to `book_root` or on the PATH)
+pub(crate) fn find_program(book_root: &Path, program: &str) -> Option<PathBuf> {
+    let exists = |path: &Path| {
+        path.is_file() || (cfg!(target_os = "windows") && path.with_extension("exe").is_file())
+    };
+
+    if program.contains('/') || program.contains('\\') {
+        let path = book_root.join(program);
+        return Some(path).filter(|path| exists(path));
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|path| exists(path))
+    })
+}
+
+/// Run a command line through the shell in `book_root` (like mdbook runs the
+/// preprocessor), returns its output (stdout and stderr)
+fn run_shell(book_root: &Path, command_line: &str) -> Result<String> {
+    let output = create_shell_command()
+        .arg(command_line)
+        .current_dir(book_root)
+        .output()?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if !output.status.success() {
+        bail!(
+            "exit code {}: {}",
+            output.status.code().unwrap_or(-9999),
+            first_line(&text)
+        );
+    }
+
+    Ok(text)
+}
+
+fn first_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map_or_else(|| String::from("no output"), String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_java_command_parsing() {
+        assert!(is_java("java"));
+        assert!(is_java("/usr/lib/jvm/bin/java"));
+        assert!(!is_java("/usr/bin/plantuml"));
+
+        assert_eq!(
+            Some("tools/plantuml.jar"),
+            get_jar("java -Xmx1g -jar tools/plantuml.jar -charset UTF-8")
+        );
+        assert_eq!(None, get_jar("java -version"));
+    }
+
+    #[test]
+    fn test_check_graphviz() {
+        let output = "Dot version: dot - graphviz version 2.43.0 (0)\n\
+                      Installation seems OK. File generation OK\n";
+        assert_eq!(
+            Finding::ok(
+                "Graphviz",
+                String::from("Dot version: dot - graphviz version 2.43.0 (0)")
+            ),
+            check_graphviz(Ok(String::from(output)))
+        );
+
+        let finding = check_graphviz(Ok(String::from(
+            "Error: No dot executable found\nDot executable does not exist\n",
+        )));
+        assert_eq!(Status::Warning, finding.status);
+        assert_eq!(
+            "PlantUML cannot use Graphviz (Error: No dot executable found)",
+            finding.details
+        );
+    }
+
+    #[test]
+    fn reports_missing_plantuml_with_a_fix() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(
+            dir.path().join("book.toml"),
+            "[preprocessor.plantuml]\nplantuml-cmd = \"/non/existing/plantuml\"\n",
+        )
+        .unwrap();
+
+        let findings = diagnose(dir.path());
+        let statuses: Vec<(&str, Status)> = findings
+            .iter()
+            .map(|f| (f.check.as_str(), f.status))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Configuration", Status::Ok),
+                ("Backend", Status::Ok),
+                ("PlantUML", Status::Error),
+                ("Image dir", Status::Ok),
+                ("Sample diagram", Status::Error),
+            ],
+            statuses
+        );
+        assert_eq!(Some(INSTALL_PLANTUML_FIX), findings[2].fix.as_deref());
+    }
+}
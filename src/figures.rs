@@ -0,0 +1,545 @@
+use crate::markdown_plantuml_pipeline::get_code_ranges;
+use crate::plantuml_renderer::{DiagramAttributes, PlantUMLRendererTrait};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Placeholder replaced with the list of all figures in the book
+pub const FIGURE_LIST_PLACEHOLDER: &str = "{{#plantuml-figures}}";
+
+/// Width of the figure thumbnails in the list of figures
+const THUMBNAIL_WIDTH: &str = "200";
+
+/// How figures (diagrams with an id or caption) are numbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FigureNumbering {
+    /// Number per chapter, prefixed with the chapter's section number (e.g.
+    /// "Figure 3.2" for the second figure in chapter 3)
+    Chapter,
+    /// Number sequentially across the whole book (e.g. "Figure 7")
+    Book,
+}
+
+impl Default for FigureNumbering {
+    fn default() -> Self {
+        Self::Chapter
+    }
+}
+
+/// A numbered diagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Figure {
+    /// The figure's id (used as HTML anchor and for references), generated
+    /// for figures without an explicit id
+    pub id: String,
+    /// The figure label (e.g. "Figure 3.2")
+    pub label: String,
+    /// The figure's caption (if any)
+    pub caption: Option<String>,
+    /// The path of the chapter containing the figure (relative to the src dir)
+    pub chapter_path: PathBuf,
+    /// The diagram source, for rendering the thumbnail in the list of figures
+    plantuml_code: String,
+    image_format: String,
+    scale: Option<String>,
+}
+
+impl Figure {
+    /// The label followed by the caption, if any (e.g. "Figure 3.2: Storage")
+    pub fn get_title(&self) -> String {
+        match &self.caption {
+            Some(caption) => format!("{}: {}", self.label, caption),
+            None => self.label.clone(),
+        }
+    }
+}
+
+/// Escape the markdown special characters in `text`, so it can be used as link
+/// text as is
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '~' | '|'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Get the positions of the list of figures placeholders in `content`,
+/// ignoring the ones in code blocks and code spans (e.g. documenting the
+/// placeholder)
+fn find_list_placeholders(content: &str) -> Vec<usize> {
+    let code_ranges = get_code_ranges(content);
+    content
+        .match_indices(FIGURE_LIST_PLACEHOLDER)
+        .map(|(pos, _)| pos)
+        .filter(|pos| !code_ranges.iter().any(|range| range.contains(pos)))
+        .collect()
+}
+
+/// True if `content` has a list of figures placeholder outside of code
+pub fn has_list_placeholder(content: &str) -> bool {
+    !find_list_placeholders(content).is_empty()
+}
+
+/// Replace the list of figures placeholders outside of code in `content` with
+/// `list`
+pub fn replace_list_placeholders(content: &str, list: &str) -> String {
+    let mut replaced = String::with_capacity(content.len() + list.len());
+    let mut pos = 0;
+    for placeholder_pos in find_list_placeholders(content) {
+        replaced.push_str(&content[pos..placeholder_pos]);
+        replaced.push_str(list);
+        pos = placeholder_pos + FIGURE_LIST_PLACEHOLDER.len();
+    }
+    replaced.push_str(&content[pos..]);
+
+    replaced
+}
+
+/// Keeps track of the figures in the book, in book order, so they can be
+/// numbered and referenced (`{{#figref id}}`).
+pub struct FigureIndex {
+    numbering: FigureNumbering,
+    figures: Vec<Figure>,
+    /// The chapter currently being processed
+    chapter_path: PathBuf,
+    /// The section number of the chapter currently being processed (None for
+    /// unnumbered chapters)
+    section_number: Option<String>,
+    /// The number of figures in the chapter currently being processed
+    chapter_figure_count: usize,
+    /// The errors found while numbering the figures (e.g. duplicate ids)
+    errors: Vec<String>,
+}
+
+impl FigureIndex {
+    pub fn new(numbering: FigureNumbering) -> Self {
+        Self {
+            numbering,
+            figures: Vec::new(),
+            chapter_path: PathBuf::new(),
+            section_number: None,
+            chapter_figure_count: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Start numbering the figures of the next chapter
+    /// # Arguments
+    /// * `chapter_path` - The path of the chapter (relative to the src dir)
+    /// * `section_number` - The chapter's section number (e.g. `[3, 1]`)
+    pub fn start_chapter(&mut self, chapter_path: &Path, section_number: Option<&[u32]>) {
+        self.chapter_path = chapter_path.to_path_buf();
+        self.section_number = section_number
+            .filter(|n| !n.is_empty())
+            .map(|n| n.iter().map(u32::to_string).collect::<Vec<_>>().join("."));
+        self.chapter_figure_count = 0;
+    }
+
+    /// Add a diagram as figure to the current chapter
+    /// # Arguments
+    /// * `plantuml_code` - The diagram source
+    /// * `image_format` - The diagram's image format
+    /// * `attributes` - The diagram's attributes (id, caption and scale are
+    ///   used)
+    pub fn add_figure(
+        &mut self,
+        plantuml_code: &str,
+        image_format: &str,
+        attributes: &DiagramAttributes,
+    ) -> Result<Figure> {
+        let id = match &attributes.id {
+            Some(id) => id.clone(),
+            None => format!("mdbook-plantuml-figure-{}", self.figures.len() + 1),
+        };
+        if let Some(existing) = self.get_figure(&id) {
+            bail!(
+                "Duplicate figure id '{}' in chapter '{}' (already used in chapter '{}').",
+                id,
+                self.chapter_path.display(),
+                existing.chapter_path.display()
+            );
+        }
+
+        self.chapter_figure_count += 1;
+        let label = match (self.numbering, &self.section_number) {
+            (FigureNumbering::Chapter, Some(section_number)) => {
+                format!("Figure {}.{}", section_number, self.chapter_figure_count)
+            }
+            (FigureNumbering::Chapter, None) => format!("Figure {}", self.chapter_figure_count),
+            (FigureNumbering::Book, _) => format!("Figure {}", self.figures.len() + 1),
+        };
+
+        let figure = Figure {
+            id,
+            label,
+            caption: attributes.caption.clone(),
+            chapter_path: self.chapter_path.clone(),
+            plantuml_code: String::from(plantuml_code),
+            image_format: String::from(image_format),
+            scale: attributes.scale.clone(),
+        };
+        self.figures.push(figure.clone());
+
+        Ok(figure)
+    }
+
+    /// Record an error found while numbering the figures, so it fails the
+    /// build once all chapters are processed
+    pub fn add_error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+
+    pub fn get_errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    fn get_figure(&self, id: &str) -> Option<&Figure> {
+        self.figures.iter().find(|figure| figure.id == id)
+    }
+
+    /// Replace the figure references (`{{#figref id}}`) in a chapter with
+    /// links to the figures. References in code blocks and inline code are
+    /// left as is, so the syntax can be shown in the book. (A backslash escape
+    /// like mdbook's own links does not work, mdbook's links preprocessor
+    /// removes the backslash before this preprocessor runs.)
+    /// Returns an error listing all the unknown figure ids.
+    pub fn replace_references(&self, content: &str, chapter_path: &Path) -> Result<String> {
+        const FIGREF: &str = "{{#figref";
+
+        let code_ranges = get_code_ranges(content);
+        let mut replaced = String::with_capacity(content.len());
+        let mut unknown_ids = Vec::new();
+        let mut pos = 0;
+        while let Some(ref_start) = content[pos..].find(FIGREF).map(|p| p + pos) {
+            let ref_end = match content[ref_start..].find("}}") {
+                Some(end) => ref_start + end + 2,
+                None => break,
+            };
+
+            if code_ranges.iter().any(|range| range.contains(&ref_start)) {
+                replaced.push_str(&content[pos..ref_end]);
+            } else {
+                replaced.push_str(&content[pos..ref_start]);
+                let id = content[ref_start + FIGREF.len()..ref_end - 2].trim();
+                match self.get_figure(id) {
+                    Some(figure) => replaced.push_str(&format!(
+                        "[{}]({})",
+                        escape_markdown(&figure.label),
+                        Self::get_figure_url(figure, chapter_path)
+                    )),
+                    None => unknown_ids.push(id),
+                }
+            }
+            pos = ref_end;
+        }
+        replaced.push_str(&content[pos..]);
+
+        if !unknown_ids.is_empty() {
+            bail!(
+                "Unknown figure id(s) referenced in chapter '{}': {}",
+                chapter_path.display(),
+                unknown_ids.join(", ")
+            );
+        }
+
+        Ok(replaced)
+    }
+
+    /// Create the list of figures (markdown) for the given chapter, every
+    /// figure is listed with its title, linking to the figure, and a thumbnail.
+    /// The chapter assets needed by the thumbnails are included.
+    /// # Arguments
+    /// * `chapter_path` - The chapter the list is added to
+    /// * `renderer` - The renderer for the thumbnails (the images are cached,
+    ///   so this does not render the diagrams again)
+    /// * `rel_img_url` - The image dir URL relative to the chapter
+    pub fn create_list(
+        &self,
+        chapter_path: &Path,
+        renderer: &impl PlantUMLRendererTrait,
+        rel_img_url: &str,
+    ) -> String {
+        let mut list = String::new();
+        for figure in &self.figures {
+            let attributes = DiagramAttributes {
+                width: Some(String::from(THUMBNAIL_WIDTH)),
+                scale: figure.scale.clone(),
+                class: Some(String::from("mdbook-plantuml-thumbnail")),
+                ..DiagramAttributes::default()
+            };
+            list.push_str(&format!(
+                "[{}]({})\n\n",
+                escape_markdown(&figure.get_title()),
+                Self::get_figure_url(figure, chapter_path)
+            ));
+            list.push_str(&renderer.render(
+                &figure.plantuml_code,
+                rel_img_url,
+                figure.image_format.clone(),
+                &attributes,
+            ));
+        }
+
+        if !list.is_empty() {
+            list.push_str(&renderer.get_chapter_assets(rel_img_url));
+        }
+
+        list
+    }
+
+    /// Get the URL of a figure relative to the given chapter
+    fn get_figure_url(figure: &Figure, chapter_path: &Path) -> String {
+        if figure.chapter_path == chapter_path {
+            return format!("#{}", figure.id);
+        }
+
+        let mut url = String::new();
+        for _ in 1..chapter_path.components().count() {
+            url.push_str("../");
+        }
+        // mdbook renders README.md chapters as index.html
+        let is_readme = figure
+            .chapter_path
+            .file_name()
+            .map_or(false, |name| name.eq_ignore_ascii_case("README.md"));
+        let html_path = if is_readme {
+            figure.chapter_path.with_file_name("index.html")
+        } else {
+            figure.chapter_path.with_extension("html")
+        };
+        let components: Vec<_> = html_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        url.push_str(&components.join("/"));
+
+        format!("{}#{}", url, figure.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn add_figure(
+        index: &mut FigureIndex,
+        id: Option<&str>,
+        caption: Option<&str>,
+    ) -> Result<Figure> {
+        let attributes = DiagramAttributes {
+            id: id.map(String::from),
+            caption: caption.map(String::from),
+            ..DiagramAttributes::default()
+        };
+        index.add_figure("A --|> B", "svg", &attributes)
+    }
+
+    fn create_index(numbering: FigureNumbering) -> FigureIndex {
+        let mut index = FigureIndex::new(numbering);
+        index.start_chapter(Path::new("intro.md"), None);
+        add_figure(&mut index, Some("overview"), None).unwrap();
+        index.start_chapter(Path::new("design/storage.md"), Some(&[3, 1]));
+        add_figure(&mut index, Some("storage-layout"), Some("Storage layout")).unwrap();
+        add_figure(&mut index, None, Some("Storage `flow` [*v2*]")).unwrap();
+
+        index
+    }
+
+    #[test]
+    fn numbers_figures_per_chapter() {
+        let index = create_index(FigureNumbering::Chapter);
+        let labels: Vec<_> = index.figures.iter().map(|f| f.label.as_str()).collect();
+        assert_eq!(vec!["Figure 1", "Figure 3.1.1", "Figure 3.1.2"], labels);
+    }
+
+    #[test]
+    fn numbers_figures_per_book() {
+        let index = create_index(FigureNumbering::Book);
+        let labels: Vec<_> = index.figures.iter().map(|f| f.label.as_str()).collect();
+        assert_eq!(vec!["Figure 1", "Figure 2", "Figure 3"], labels);
+    }
+
+    #[test]
+    fn rejects_duplicate_ids() {
+        let mut index = create_index(FigureNumbering::Chapter);
+        assert!(add_figure(&mut index, Some("overview"), None).is_err());
+    }
+
+    #[test]
+    fn generates_missing_ids() {
+        let index = create_index(FigureNumbering::Chapter);
+        assert_eq!("mdbook-plantuml-figure-3", index.figures[2].id);
+        assert_eq!(
+            "Figure 3.1.2: Storage `flow` [*v2*]",
+            index.figures[2].get_title()
+        );
+    }
+
+    struct ThumbnailRenderer;
+
+    impl PlantUMLRendererTrait for ThumbnailRenderer {
+        fn render(
+            &self,
+            plantuml_code: &str,
+            rel_img_url: &str,
+            image_format: String,
+            attributes: &DiagramAttributes,
+        ) -> String {
+            format!(
+                "{} {} {} {:?}\n\n",
+                plantuml_code, rel_img_url, image_format, attributes.width
+            )
+        }
+    }
+
+    #[test]
+    fn creates_list_of_figures() {
+        let index = create_index(FigureNumbering::Chapter);
+        assert_eq!(
+            "[Figure 1](../intro.html#overview)\n\n\
+             A --|> B ../img svg Some(\"200\")\n\n\
+             [Figure 3.1.1: Storage layout](../design/storage.html#storage-layout)\n\n\
+             A --|> B ../img svg Some(\"200\")\n\n\
+             [Figure 3.1.2: Storage \\`flow\\` \\[\\*v2\\*\\]](../design/storage.html#mdbook-plantuml-figure-3)\n\n\
+             A --|> B ../img svg Some(\"200\")\n\n",
+            index.create_list(
+                Path::new("appendix/figures.md"),
+                &ThumbnailRenderer {},
+                "../img"
+            )
+        );
+    }
+
+    #[test]
+    fn replaces_references() {
+        let index = create_index(FigureNumbering::Chapter);
+        assert_eq!(
+            "See [Figure 3.1.1](design/storage.html#storage-layout) and [Figure 1](#overview).",
+            index
+                .replace_references(
+                    "See {{#figref storage-layout}} and {{#figref overview }}.",
+                    Path::new("intro.md")
+                )
+                .unwrap()
+        );
+        assert_eq!(
+            "[Figure 1](../intro.html#overview)",
+            index
+                .replace_references("{{#figref overview}}", Path::new("design/storage.md"))
+                .unwrap()
+        );
+        // References in code are left as is
+        let code = "Use `{{#figref id}}` to reference a figure:\n\n\
+                    ```markdown\nSee {{#figref id}}.\n```\n\n    {{#figref id}}\n";
+        assert_eq!(
+            code,
+            index
+                .replace_references(code, Path::new("intro.md"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn refers_to_readme_chapters_as_index() {
+        let mut index = FigureIndex::new(FigureNumbering::Book);
+        index.start_chapter(Path::new("README.md"), None);
+        add_figure(&mut index, Some("overview"), None).unwrap();
+        index.start_chapter(Path::new("design/readme.md"), None);
+        add_figure(&mut index, Some("storage-layout"), None).unwrap();
+
+        assert_eq!(
+            "[Figure 1](../index.html#overview), [Figure 2](../design/index.html#storage-layout)",
+            index
+                .replace_references(
+                    "{{#figref overview}}, {{#figref storage-layout}}",
+                    Path::new("design/storage.md")
+                )
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn replaces_references_after_the_links_preprocessor() {
+        use mdbook::book::{Book, BookItem, Chapter};
+        use mdbook::preprocess::{LinkPreprocessor, Preprocessor, PreprocessorContext};
+
+        let index = create_index(FigureNumbering::Chapter);
+        let dir = tempfile::tempdir().unwrap();
+        let ctx: PreprocessorContext = serde_json::from_value(serde_json::json!({
+            "root": dir.path(),
+            "config": {},
+            "renderer": "html",
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        }))
+        .unwrap();
+        let content = "See {{#figref overview}}, written as `\\{{#figref overview}}`:\n\n\
+                       ```markdown\n\\{{#figref unknown}}\n```\n";
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Intro",
+            String::from(content),
+            "intro.md",
+            Vec::new(),
+        ));
+
+        // mdbook's links preprocessor runs first, and removes the backslashes
+        let book = LinkPreprocessor::new().run(&ctx, book).unwrap();
+        let content = match book.iter().next() {
+            Some(BookItem::Chapter(chapter)) => chapter.content.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            "See [Figure 1](#overview), written as `{{#figref overview}}`:\n\n\
+             ```markdown\n{{#figref unknown}}\n```\n",
+            index
+                .replace_references(&content, Path::new("intro.md"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn replaces_list_placeholders_outside_of_code() {
+        let content = "# Figures\n\n{{#plantuml-figures}}\n\n\
+                       Add `{{#plantuml-figures}}` to a chapter:\n\n\
+                       ```md\n{{#plantuml-figures}}\n```\n";
+        assert!(has_list_placeholder(content));
+        assert_eq!(
+            "# Figures\n\n- Figure 1\n\n\
+             Add `{{#plantuml-figures}}` to a chapter:\n\n\
+             ```md\n{{#plantuml-figures}}\n```\n",
+            replace_list_placeholders(content, "- Figure 1")
+        );
+
+        let documentation = "Use `{{#plantuml-figures}}`.\n";
+        assert!(!has_list_placeholder(documentation));
+        assert_eq!(
+            documentation,
+            replace_list_placeholders(documentation, "- Figure 1")
+        );
+    }
+
+    #[test]
+    fn fails_on_unknown_references() {
+        let index = create_index(FigureNumbering::Chapter);
+        let error = index
+            .replace_references(
+                "{{#figref foo}} {{#figref overview}} {{#figref bar}}",
+                Path::new("intro.md"),
+            )
+            .unwrap_err();
+        assert_eq!(
+            "Unknown figure id(s) referenced in chapter 'intro.md': foo, bar",
+            error.to_string()
+        );
+    }
+}
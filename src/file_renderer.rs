@@ -0,0 +1,204 @@
+use crate::figures::FigureIndex;
+use crate::link_rewriter::ChapterLinkRewriter;
+use crate::markdown_plantuml_pipeline::{get_diagrams, render_plantuml_code_blocks};
+use crate::plantuml_renderer::PlantUMLRenderer;
+use crate::plantumlconfig::{PlantUMLConfig, ZoomMode};
+use crate::{get_plantuml_config, load_book_config};
+use anyhow::{bail, Context, Result};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Render a single markdown file (i.e. its PlantUML code blocks) or PlantUML
+/// source file outside of mdbook, using the configuration of the book in
+/// `book_root`. Everything is written to `output_dir`, for markdown files this
+/// is the rendered markdown and its images (and the lightbox assets when the
+/// lightbox zoom mode is enabled). Returns the paths of the rendered markdown
+/// file and the assets it uses, or the diagram's image files.
+/// # Arguments
+/// * `image_format` - The image format for PlantUML source files (svg by
+///   default), code blocks in markdown files use the format in their info
+///   string.
+pub fn render_file(
+    book_root: &Path,
+    input_file: &Path,
+    output_dir: &Path,
+    image_format: Option<&str>,
+) -> Result<Vec<PathBuf>> {
+    let cfg = get_plantuml_config(&load_book_config(book_root)?);
+    let source = fs::read_to_string(input_file)
+        .with_context(|| format!("Failed to read {:?}", input_file))?;
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create the output dir {:?}", output_dir))?;
+
+    let renderer = PlantUMLRenderer::new(&cfg, output_dir);
+    // The output dir is not ours, leave the files that are already there alone
+    renderer.disable_cleanup();
+
+    if is_markdown(input_file) {
+        render_markdown(&renderer, &cfg, &source, input_file, output_dir)
+    } else {
+        let image_format = if source.contains("@startditaa") {
+            // ditaa only supports png
+            "png"
+        } else {
+            image_format.unwrap_or("svg")
+        };
+        renderer.render_images(&source, image_format)
+    }
+}
+
+fn is_markdown(file: &Path) -> bool {
+    let extension = file.extension().unwrap_or_default();
+    extension == "md" || extension == "markdown"
+}
+
+/// Render the PlantUML code blocks of a markdown file like the preprocessor
+/// does for a chapter in the book's root dir. The images are referred to
+/// relative to the rendered markdown file.
+fn render_markdown(
+    renderer: &PlantUMLRenderer,
+    cfg: &PlantUMLConfig,
+    markdown: &str,
+    input_file: &Path,
+    output_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let file_name = input_file
+        .file_name()
+        .with_context(|| format!("{:?} is not a file", input_file))?;
+    let output_file = output_dir.join(file_name);
+    if is_same_file(input_file, &output_file) {
+        bail!(
+            "Rendering {:?} would overwrite it, please use another output dir.",
+            input_file
+        );
+    }
+
+    let chapter_path = Path::new(file_name);
+    let chapters: HashSet<PathBuf> = std::iter::once(chapter_path.to_path_buf()).collect();
+    let link_rewriter = ChapterLinkRewriter::new(chapter_path, &chapters);
+    let figures = RefCell::new(FigureIndex::new(cfg.figure_numbering));
+    figures.borrow_mut().start_chapter(chapter_path, None);
+
+    let rendered = render_plantuml_code_blocks(markdown, renderer, ".", &link_rewriter, &figures);
+    let figures = figures.into_inner();
+    if let Some(error) = figures.get_errors().first() {
+        bail!("{}", error);
+    }
+    let rendered = figures.replace_references(&rendered, chapter_path)?;
+    fs::write(&output_file, rendered)
+        .with_context(|| format!("Failed to write {:?}", output_file))?;
+
+    let mut files = vec![output_file];
+    // The rendered markdown refers to the lightbox assets next to its images
+    if cfg.zoom == Some(ZoomMode::Lightbox) && !get_diagrams(markdown).is_empty() {
+        files.extend(renderer.write_lightbox_assets());
+    }
+
+    Ok(files)
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plantuml_renderer::get_image_filename;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn renders_markdown_without_diagrams() {
+        let dir = tempdir().unwrap();
+        let input_file = dir.path().join("chapter.md");
+        fs::write(&input_file, "# Chapter\n\nNo diagrams here.\n").unwrap();
+        let output_dir = dir.path().join("out");
+
+        let files = render_file(dir.path(), &input_file, &output_dir, None).unwrap();
+        assert_eq!(vec![output_dir.join("chapter.md")], files);
+        assert_eq!(
+            "# Chapter\n\nNo diagrams here.\n",
+            fs::read_to_string(&files[0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn does_not_overwrite_the_input_file() {
+        let dir = tempdir().unwrap();
+        let input_file = dir.path().join("chapter.md");
+        fs::write(&input_file, "# Chapter\n").unwrap();
+
+        assert!(render_file(dir.path(), &input_file, dir.path(), None).is_err());
+        assert_eq!("# Chapter\n", fs::read_to_string(&input_file).unwrap());
+    }
+
+    #[test]
+    fn reports_diagram_rendering_errors() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("book.toml"),
+            "[preprocessor.plantuml]\nplantuml-cmd = \"/non/existing/plantuml\"\n",
+        )
+        .unwrap();
+        let input_file = dir.path().join("diagram.puml");
+        fs::write(&input_file, "@startuml\nA --|> B\n@enduml\n").unwrap();
+        let existing_file = dir.path().join("existing.txt");
+        fs::write(&existing_file, "").unwrap();
+
+        assert!(render_file(dir.path(), &input_file, dir.path(), None).is_err());
+        // Files in the output dir are left alone
+        assert!(existing_file.exists());
+    }
+
+    #[test]
+    fn writes_the_lightbox_assets_used_by_the_markdown() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("book.toml"),
+            "[preprocessor.plantuml]\nplantuml-cmd = \"/non/existing/plantuml\"\nzoom = \"lightbox\"\n",
+        )
+        .unwrap();
+        let source = "@startuml\nA --|> B\n@enduml\n";
+        let input_file = dir.path().join("chapter.md");
+        fs::write(
+            &input_file,
+            format!("# Chapter\n\n```plantuml\n{}```\n", source),
+        )
+        .unwrap();
+        // Use a cached image, so PlantUML is not needed
+        let output_dir = dir.path().join("out");
+        fs::create_dir(&output_dir).unwrap();
+        let image_file = get_image_filename(&output_dir, source, "svg");
+        fs::write(&image_file, "<svg/>").unwrap();
+
+        let files = render_file(dir.path(), &input_file, &output_dir, None).unwrap();
+        let js_file = output_dir.join("mdbook-plantuml-lightbox.js");
+        let css_file = output_dir.join("mdbook-plantuml-lightbox.css");
+        assert_eq!(
+            vec![
+                output_dir.join("chapter.md"),
+                js_file.clone(),
+                css_file.clone()
+            ],
+            files
+        );
+        let rendered = fs::read_to_string(&files[0]).unwrap();
+        assert!(rendered.contains("href=\"./mdbook-plantuml-lightbox.css\""));
+        assert!(rendered.contains("src=\"./mdbook-plantuml-lightbox.js\""));
+
+        let mut output_files: Vec<PathBuf> = fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        output_files.sort();
+        let mut expected = vec![files[0].clone(), image_file, js_file, css_file];
+        expected.sort();
+        assert_eq!(expected, output_files);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::svg_postprocessor::{find_svg_tag, get_attribute};
+use std::fs;
+use std::path::Path;
+
+pub(crate) const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Get the intrinsic size (width, height) in pixels of an svg or png image.
+/// Returns None for other formats, or when the size cannot be determined.
+pub fn get_image_size(image_path: &Path) -> Option<(u32, u32)> {
+    let file_name = image_path.file_name()?.to_string_lossy();
+    if file_name.ends_with(".svg") {
+        get_svg_size(&fs::read_to_string(image_path).ok()?)
+    } else if file_name.ends_with(".png") {
+        get_png_size(&fs::read(image_path).ok()?)
+    } else {
+        None
+    }
+}
+
+/// Get the size of an svg image from its viewBox, or its width and height
+/// attributes when there is no viewBox
+fn get_svg_size(svg: &str) -> Option<(u32, u32)> {
+    let (tag_start, tag_end) = find_svg_tag(svg)?;
+    let tag = &svg[tag_start..tag_end];
+
+    if let Some(view_box) = get_attribute(tag, "viewBox") {
+        let values: Vec<f64> = view_box
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|v| !v.is_empty())
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        if let [_, _, width, height] = values[..] {
+            return to_size(width, height);
+        }
+    }
+
+    let parse = |name| -> Option<f64> {
+        get_attribute(tag, name)?
+            .trim_end_matches("px")
+            .parse()
+            .ok()
+    };
+    to_size(parse("width")?, parse("height")?)
+}
+
+fn to_size(width: f64, height: f64) -> Option<(u32, u32)> {
+    if width > 0.0 && height > 0.0 {
+        Some((width.round() as u32, height.round() as u32))
+    } else {
+        None
+    }
+}
+
+/// Get the size of a png image from its header (the IHDR chunk, which always
+/// comes first)
+fn get_png_size(png: &[u8]) -> Option<(u32, u32)> {
+    if png.len() < 24 || !png.starts_with(PNG_SIGNATURE) || &png[12..16] != b"IHDR" {
+        return None;
+    }
+
+    let read_u32 = |offset: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&png[offset..offset + 4]);
+        u32::from_be_bytes(bytes)
+    };
+    Some((read_u32(16), read_u32(20)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_get_svg_size() {
+        assert_eq!(
+            Some((300, 121)),
+            get_svg_size("<svg width=\"600px\" viewBox=\"0 0 300 120.6\"><g/></svg>")
+        );
+        assert_eq!(
+            Some((30, 12)),
+            get_svg_size("<svg width=\"30px\" height=\"12\"/>")
+        );
+        assert_eq!(None, get_svg_size("<svg width=\"30px\"/>"));
+        assert_eq!(None, get_svg_size("<html/>"));
+    }
+
+    #[test]
+    fn test_get_png_size() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&[0, 0, 0, 13]);
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        assert_eq!(Some((640, 480)), get_png_size(&png));
+
+        assert_eq!(None, get_png_size(&png[..20]));
+        assert_eq!(None, get_png_size(b"GIF89a, not a png image at all"));
+    }
+
+    #[test]
+    fn test_get_image_size() {
+        let dir = tempdir().unwrap();
+        let svg_file = dir.path().join("foo.svg");
+        fs::write(&svg_file, "<svg viewBox=\"0 0 10 20\"/>").unwrap();
+        assert_eq!(Some((10, 20)), get_image_size(&svg_file));
+
+        let txt_file = dir.path().join("foo.atxt");
+        fs::write(&txt_file, "<svg viewBox=\"0 0 10 20\"/>").unwrap();
+        assert_eq!(None, get_image_size(&txt_file));
+        assert_eq!(None, get_image_size(&dir.path().join("missing.svg")));
+    }
+}
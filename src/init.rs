@@ -0,0 +1,241 @@
+use crate::doctor::find_program;
+use crate::get_img_output_dir;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The PlantUML server URLs tried when there is no local PlantUML install
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+const LOCAL_SERVER_URLS: [&str; 2] = ["http://localhost:8080/plantuml", "http://localhost:8080"];
+
+/// The changes made to a book by `init_book`
+pub struct InitResult {
+    /// The detected PlantUML command (or server URL), None if no PlantUML
+    /// install or server was found (or book.toml was not changed)
+    pub plantuml_cmd: Option<String>,
+    /// False if book.toml already had a `[preprocessor.plantuml]` table
+    pub added_preprocessor: bool,
+    /// The entry added to .gitignore, None if it was ignored already
+    pub gitignore_entry: Option<String>,
+}
+
+/// Add the preprocessor to the book in `book_root`: a `[preprocessor.plantuml]`
+/// table is appended to its book.toml, with the `plantuml-cmd` of the detected
+/// PlantUML install or server, and the image dir is added to its .gitignore.
+/// book.toml is extended as text, so its formatting and comments are kept.
+pub fn init_book(book_root: &Path) -> Result<InitResult> {
+    init_book_with_detector(book_root, detect_plantuml_cmd)
+}
+
+/// `init_book` using `detect` to find the PlantUML command of the book
+fn init_book_with_detector(
+    book_root: &Path,
+    detect: impl Fn(&Path) -> Option<String>,
+) -> Result<InitResult> {
+    let config_file = book_root.join("book.toml");
+    if !config_file.exists() {
+        bail!(
+            "{:?} not found, run `mdbook init` first (or pass the book's root dir).",
+            config_file
+        );
+    }
+    let book_toml = fs::read_to_string(&config_file)
+        .with_context(|| format!("Failed to read {:?}", config_file))?;
+    let config = mdbook::Config::from_str(&book_toml)
+        .with_context(|| format!("Invalid configuration file {:?}", config_file))?;
+
+    let added_preprocessor = config.get("preprocessor.plantuml").is_none();
+    let plantuml_cmd = if added_preprocessor {
+        let plantuml_cmd = detect(book_root);
+        let book_toml = add_preprocessor_table(&book_toml, plantuml_cmd.as_deref());
+        fs::write(&config_file, book_toml)
+            .with_context(|| format!("Failed to write {:?}", config_file))?;
+        plantuml_cmd
+    } else {
+        None
+    };
+
+    let img_dir = get_img_output_dir(Path::new(""), &config);
+    let entry = img_dir.to_string_lossy().replace('\\', "/");
+    let gitignore_file = book_root.join(".gitignore");
+    let gitignore = match fs::read_to_string(&gitignore_file) {
+        Ok(gitignore) => gitignore,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", gitignore_file)),
+    };
+    let gitignore_entry = match add_gitignore_entry(&gitignore, &entry) {
+        Some(gitignore) => {
+            fs::write(&gitignore_file, gitignore)
+                .with_context(|| format!("Failed to write {:?}", gitignore_file))?;
+            Some(entry)
+        }
+        None => None,
+    };
+
+    Ok(InitResult {
+        plantuml_cmd,
+        added_preprocessor,
+        gitignore_entry,
+    })
+}
+
+/// Find a PlantUML install or server, in order of preference: a `plantuml`
+/// binary on the PATH, a plantuml.jar in the book's root dir, or a PlantUML
+/// server on localhost.
+fn detect_plantuml_cmd(book_root: &Path) -> Option<String> {
+    if find_program(book_root, "plantuml").is_some() {
+        return Some(String::from("plantuml"));
+    }
+
+    if book_root.join("plantuml.jar").is_file() {
+        // The preprocessor runs in the book's root dir
+        return Some(String::from("java -jar plantuml.jar"));
+    }
+
+    detect_local_server()
+}
+
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+fn detect_local_server() -> Option<String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(2))
+        .build()
+        .ok()?;
+    LOCAL_SERVER_URLS
+        .iter()
+        .find(|url| {
+            client
+                .get(**url)
+                .send()
+                .map_or(false, |response| response.status().is_success())
+        })
+        .map(|url| String::from(*url))
+}
+
+#[cfg(not(any(feature = "plantuml-ssl-server", feature = "plantuml-server")))]
+fn detect_local_server() -> Option<String> {
+    None
+}
+
+/// Append the `[preprocessor.plantuml]` table to the book.toml text. When no
+/// PlantUML command was detected a commented out example is added instead.
+fn add_preprocessor_table(book_toml: &str, plantuml_cmd: Option<&str>) -> String {
+    let mut book_toml = String::from(book_toml);
+    if !book_toml.is_empty() && !book_toml.ends_with('\n') {
+        book_toml.push('\n');
+    }
+    if !book_toml.is_empty() && !book_toml.ends_with("\n\n") {
+        book_toml.push('\n');
+    }
+
+    book_toml.push_str("[preprocessor.plantuml]\n");
+    match plantuml_cmd {
+        Some(cmd) => book_toml.push_str(&format!("plantuml-cmd = \"{}\"\n", cmd)),
+        None => book_toml.push_str(
+            "# No PlantUML install or server was found, set the PlantUML command or server URL\n\
+             # plantuml-cmd = \"java -jar /path/to/plantuml.jar\"\n",
+        ),
+    }
+
+    book_toml
+}
+
+/// Add `entry` to the .gitignore text, returns None if it is ignored already
+fn add_gitignore_entry(gitignore: &str, entry: &str) -> Option<String> {
+    let is_entry = |line: &str| line.trim().trim_matches('/') == entry;
+    if gitignore.lines().any(is_entry) {
+        return None;
+    }
+
+    let mut gitignore = String::from(gitignore);
+    if !gitignore.is_empty() && !gitignore.ends_with('\n') {
+        gitignore.push('\n');
+    }
+    gitignore.push_str(entry);
+    gitignore.push('\n');
+
+    Some(gitignore)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_add_preprocessor_table() {
+        assert_eq!(
+            "[book]\ntitle = \"Book\" # The title\n\n[preprocessor.plantuml]\nplantuml-cmd = \"plantuml\"\n",
+            add_preprocessor_table("[book]\ntitle = \"Book\" # The title", Some("plantuml"))
+        );
+        assert_eq!(
+            "[book]\n\n[preprocessor.plantuml]\nplantuml-cmd = \"plantuml\"\n",
+            add_preprocessor_table("[book]\n\n", Some("plantuml"))
+        );
+
+        let book_toml = add_preprocessor_table("[book]\n", None);
+        let config = mdbook::Config::from_str(&book_toml).unwrap();
+        assert!(config.get("preprocessor.plantuml").is_some());
+        assert!(config.get("preprocessor.plantuml.plantuml-cmd").is_none());
+    }
+
+    #[test]
+    fn test_add_gitignore_entry() {
+        assert_eq!(
+            Some(String::from("book\nsrc/mdbook-plantuml-img\n")),
+            add_gitignore_entry("book", "src/mdbook-plantuml-img")
+        );
+        assert_eq!(
+            Some(String::from("src/mdbook-plantuml-img\n")),
+            add_gitignore_entry("", "src/mdbook-plantuml-img")
+        );
+        assert_eq!(
+            None,
+            add_gitignore_entry(
+                "book\n/src/mdbook-plantuml-img/\n",
+                "src/mdbook-plantuml-img"
+            )
+        );
+    }
+
+    #[test]
+    fn adds_the_preprocessor_once() {
+        let detect = |_: &Path| Some(String::from("java -jar plantuml.jar"));
+        let dir = tempdir().unwrap();
+        assert!(init_book_with_detector(dir.path(), detect).is_err());
+
+        let book_toml = "[book]\n# The book's title\ntitle = \"Book\"\nsrc = \"text\"\n";
+        fs::write(dir.path().join("book.toml"), book_toml).unwrap();
+        fs::write(dir.path().join(".gitignore"), "book\n").unwrap();
+
+        let result = init_book_with_detector(dir.path(), detect).unwrap();
+        assert!(result.added_preprocessor);
+        assert_eq!(
+            Some(String::from("java -jar plantuml.jar")),
+            result.plantuml_cmd
+        );
+        assert_eq!(
+            Some(String::from("text/mdbook-plantuml-img")),
+            result.gitignore_entry
+        );
+        let updated = fs::read_to_string(dir.path().join("book.toml")).unwrap();
+        assert!(updated.starts_with(book_toml));
+        assert!(updated
+            .ends_with("[preprocessor.plantuml]\nplantuml-cmd = \"java -jar plantuml.jar\"\n"));
+        assert_eq!(
+            "book\ntext/mdbook-plantuml-img\n",
+            fs::read_to_string(dir.path().join(".gitignore")).unwrap()
+        );
+
+        let result =
+            init_book_with_detector(dir.path(), |_| panic!("Not expected to detect")).unwrap();
+        assert!(!result.added_preprocessor);
+        assert_eq!(None, result.gitignore_entry);
+        assert_eq!(
+            updated,
+            fs::read_to_string(dir.path().join("book.toml")).unwrap()
+        );
+    }
+}
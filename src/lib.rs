@@ -1,7 +1,19 @@
 #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
 mod base64_plantuml;
+mod book_checker;
+mod build_report;
+mod cache;
+mod diagram_text;
 mod dir_cleaner;
+mod doctor;
+mod figures;
+mod file_renderer;
+mod image_size;
+mod init;
+mod link_rewriter;
+mod log_level;
 mod markdown_plantuml_pipeline;
+mod mdbook_version;
 mod plantuml_backend;
 mod plantuml_backend_factory;
 mod plantuml_renderer;
@@ -9,16 +21,39 @@ mod plantuml_renderer;
 mod plantuml_server_backend;
 mod plantuml_shell_backend;
 mod plantumlconfig;
+mod progress;
+mod svg_postprocessor;
 mod util;
 
-use crate::markdown_plantuml_pipeline::render_plantuml_code_blocks;
+use crate::figures::{
+    has_list_placeholder, replace_list_placeholders, FigureIndex, FIGURE_LIST_PLACEHOLDER,
+};
+use crate::link_rewriter::ChapterLinkRewriter;
+use crate::markdown_plantuml_pipeline::{get_diagrams, render_plantuml_code_blocks};
 
 use crate::plantuml_renderer::PlantUMLRenderer;
-use crate::plantumlconfig::PlantUMLConfig;
-use mdbook::book::{Book, BookItem};
+use crate::plantumlconfig::{PlantUMLConfig, ZoomMode};
+use anyhow::Context;
+use mdbook::book::{Book, BookItem, Chapter, MDBook};
 use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+pub use crate::book_checker::{check_book, CheckResult, Diagnostic};
+pub use crate::cache::{
+    clear_cache, format_size, get_cache_stats, prune_cache, verify_cache, CacheStats, PruneResult,
+    VerifyResult,
+};
+pub use crate::doctor::{diagnose, Finding, Status};
+pub use crate::file_renderer::render_file;
+pub use crate::init::{init_book, InitResult};
+pub use crate::log_level::{get_config_log_level, get_env_log_level};
+pub use crate::mdbook_version::check_mdbook_version;
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+pub use crate::plantuml_server_backend::{decode_diagram, encode_diagram};
+pub use crate::progress::clear_progress_line;
 
 pub struct PlantUMLPreprocessor;
 
@@ -32,15 +67,12 @@ impl Preprocessor for PlantUMLPreprocessor {
         ctx: &PreprocessorContext,
         mut book: Book,
     ) -> Result<Book, mdbook::errors::Error> {
-        let cfg = get_plantuml_config(ctx);
-        let img_output_dir = &ctx
-            .root
-            .join(&ctx.config.book.src)
-            .join("mdbook-plantuml-img");
+        let cfg = get_plantuml_config(&ctx.config);
+        let img_output_dir = &get_img_output_dir(&ctx.root, &ctx.config);
 
         // Always create the image output dir
         if !img_output_dir.exists() {
-            if let Err(e) = fs::create_dir_all(&img_output_dir) {
+            if let Err(e) = fs::create_dir_all(img_output_dir) {
                 return Err(mdbook::errors::Error::msg(format!(
                     "Failed to create the image output dir ({}).",
                     e
@@ -49,18 +81,21 @@ impl Preprocessor for PlantUMLPreprocessor {
         }
 
         let renderer = PlantUMLRenderer::new(&cfg, img_output_dir);
-        let res = None;
-        book.for_each_mut(|item: &mut BookItem| {
-            if let BookItem::Chapter(ref mut chapter) = *item {
-                if let Some(chapter_path) = &chapter.path {
-                    let rel_image_url = get_relative_img_url(chapter_path);
-                    chapter.content =
-                        render_plantuml_code_blocks(&chapter.content, &renderer, &rel_image_url);
-                }
+        if cfg.zoom == Some(ZoomMode::Lightbox) {
+            renderer.write_lightbox_assets();
+        }
+        renderer.show_progress(count_diagrams(&book));
+        let errors = render_book(&mut book, &cfg, &renderer);
+        if let Some(report_file) = &cfg.build_report {
+            if let Err(e) = renderer.write_report(&ctx.root.join(report_file)) {
+                log::error!("{:#}", e);
             }
-        });
-
-        res.unwrap_or(Ok(())).map(|_| book)
+        }
+        if errors.is_empty() {
+            Ok(book)
+        } else {
+            Err(mdbook::errors::Error::msg(errors.join("\n")))
+        }
     }
 
     fn supports_renderer(&self, renderer: &str) -> bool {
@@ -68,6 +103,77 @@ impl Preprocessor for PlantUMLPreprocessor {
     }
 }
 
+/// Render the diagrams in all chapters of the book, and resolve the figure
+/// references and lists. Returns the errors (e.g. unknown figure references).
+fn render_book(book: &mut Book, cfg: &PlantUMLConfig, renderer: &PlantUMLRenderer) -> Vec<String> {
+    let chapters = get_chapter_paths(book);
+    let figures = RefCell::new(FigureIndex::new(cfg.figure_numbering));
+    book.for_each_mut(|item: &mut BookItem| {
+        if let BookItem::Chapter(ref mut chapter) = *item {
+            if let Some(chapter_path) = &chapter.path {
+                let rel_image_url = get_relative_img_url(chapter_path);
+                let link_rewriter = ChapterLinkRewriter::new(chapter_path, &chapters);
+                renderer.start_chapter(chapter_path);
+                figures
+                    .borrow_mut()
+                    .start_chapter(chapter_path, chapter.number.as_ref().map(|n| n.as_slice()));
+                chapter.content = render_plantuml_code_blocks(
+                    &chapter.content,
+                    renderer,
+                    &rel_image_url,
+                    &link_rewriter,
+                    &figures,
+                );
+            }
+        }
+    });
+
+    if let Some(title) = &cfg.list_of_figures {
+        add_figure_list_chapter(book, title, &chapters);
+    }
+
+    // Figure references and lists are resolved once all figures are known,
+    // so chapters can refer to figures in later chapters
+    let figures = figures.into_inner();
+    let mut errors = figures.get_errors().to_vec();
+    book.for_each_mut(|item: &mut BookItem| {
+        if let BookItem::Chapter(ref mut chapter) = *item {
+            if let Some(chapter_path) = &chapter.path {
+                match figures.replace_references(&chapter.content, chapter_path) {
+                    Ok(content) => chapter.content = content,
+                    Err(e) => errors.push(e.to_string()),
+                }
+
+                if has_list_placeholder(&chapter.content) {
+                    let rel_image_url = get_relative_img_url(chapter_path);
+                    let list = figures.create_list(chapter_path, renderer, &rel_image_url);
+                    chapter.content = replace_list_placeholders(&chapter.content, &list);
+                }
+            }
+        }
+    });
+
+    errors
+}
+
+/// Count the diagrams in the chapters rendered by `render_book`
+fn count_diagrams(book: &Book) -> usize {
+    book.iter()
+        .map(|item| match item {
+            BookItem::Chapter(chapter) if chapter.path.is_some() => {
+                get_diagrams(&chapter.content).len()
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Get the dir the images are written to (in the book's source dir, so mdbook
+/// copies them to the output)
+fn get_img_output_dir(book_root: &Path, config: &mdbook::Config) -> PathBuf {
+    book_root.join(&config.book.src).join("mdbook-plantuml-img")
+}
+
 fn get_relative_img_url(chapter_path: &Path) -> String {
     let nesting_level = chapter_path.components().count();
     let mut rel_image_url = String::new();
@@ -79,8 +185,62 @@ fn get_relative_img_url(chapter_path: &Path) -> String {
     rel_image_url
 }
 
-fn get_plantuml_config(ctx: &PreprocessorContext) -> PlantUMLConfig {
-    ctx.config
+/// Add a chapter containing the list of figures to the end of the book
+fn add_figure_list_chapter(book: &mut Book, title: &str, chapters: &HashSet<PathBuf>) {
+    let path = PathBuf::from("plantuml-figures.md");
+    if chapters.contains(&path) {
+        log::warn!(
+            "Not adding the list of figures, the book already has a chapter named {:?}.",
+            path
+        );
+        return;
+    }
+
+    let content = format!("# {}\n\n{}\n", title, FIGURE_LIST_PLACEHOLDER);
+    let mut chapter = Chapter::new(title, content, path, Vec::new());
+    // The chapter is generated, there is no source file
+    chapter.source_path = None;
+    book.push_item(chapter);
+}
+
+/// Get the paths (relative to the src dir) of all the chapters in the book
+fn get_chapter_paths(book: &Book) -> HashSet<PathBuf> {
+    book.iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(chapter) => chapter.path.clone(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Load the configuration of the book in `book_root` (i.e. its book.toml). The
+/// default configuration is used when the book has no book.toml.
+fn load_book_config(book_root: &Path) -> anyhow::Result<mdbook::Config> {
+    let config_file = book_root.join("book.toml");
+    if config_file.exists() {
+        mdbook::Config::from_disk(&config_file)
+            .with_context(|| format!("Failed to load {:?}", config_file))
+    } else {
+        log::warn!(
+            "No book.toml found in {:?}, using the default configuration.",
+            book_root
+        );
+        Ok(mdbook::Config::default())
+    }
+}
+
+/// Load the book in `book_root` without modifying it (i.e. without creating
+/// missing chapters)
+fn load_book(book_root: &Path) -> anyhow::Result<MDBook> {
+    let mut config = load_book_config(book_root)?;
+    config.update_from_env();
+    config.build.create_missing = false;
+    MDBook::load_with_config(book_root, config)
+        .with_context(|| format!("Failed to load the book in {:?}", book_root))
+}
+
+fn get_plantuml_config(config: &mdbook::Config) -> PlantUMLConfig {
+    config
         .get("preprocessor.plantuml")
         .and_then(|raw| {
             raw.clone()
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Rewrites links to chapters (.md files) in PlantUML hyperlinks (`[[...]]`)
+/// to the .html file mdbook generates for the chapter. The rewritten links are
+/// relative to the image dir (where the diagram images are stored), so they
+/// work from within the diagram.
+///
+/// # Example:
+/// In chapter `guide/intro.md` the link `[[../design/storage.md#layout]]` is
+/// rewritten to `[[../design/storage.html#layout]]` (i.e. relative to
+/// `mdbook-plantuml-img/`).
+pub struct ChapterLinkRewriter<'a> {
+    /// The path of the chapter containing the diagrams (relative to the src dir)
+    chapter_path: &'a Path,
+    /// The paths of all the chapters in the book (relative to the src dir)
+    chapters: &'a HashSet<PathBuf>,
+}
+
+impl<'a> ChapterLinkRewriter<'a> {
+    pub const fn new(chapter_path: &'a Path, chapters: &'a HashSet<PathBuf>) -> Self {
+        Self {
+            chapter_path,
+            chapters,
+        }
+    }
+
+    /// Rewrite all the chapter links in the given PlantUML source
+    pub fn rewrite(&self, plantuml_code: &str) -> String {
+        let mut rewritten = String::with_capacity(plantuml_code.len());
+        let mut pos = 0;
+        while let Some(link_start) = plantuml_code[pos..].find("[[").map(|p| p + pos + 2) {
+            // The link target ends at the first space, tooltip or the end of the
+            // link
+            let target_len = plantuml_code[link_start..]
+                .find(|c: char| c.is_whitespace() || c == '{' || c == ']')
+                .unwrap_or(plantuml_code.len() - link_start);
+            let target = &plantuml_code[link_start..link_start + target_len];
+
+            rewritten.push_str(&plantuml_code[pos..link_start]);
+            match self.rewrite_target(target) {
+                Some(new_target) => rewritten.push_str(&new_target),
+                None => rewritten.push_str(target),
+            }
+            pos = link_start + target_len;
+        }
+        rewritten.push_str(&plantuml_code[pos..]);
+
+        rewritten
+    }
+
+    /// Get the image dir relative URL for a chapter link, or None if the
+    /// target is not a (relative) link to a .md file
+    fn rewrite_target(&self, target: &str) -> Option<String> {
+        if target.contains("://") || target.starts_with('/') || target.starts_with("mailto:") {
+            return None;
+        }
+
+        let path_end = target
+            .find(|c| c == '#' || c == '?')
+            .unwrap_or(target.len());
+        let (path, suffix) = target.split_at(path_end);
+        if !path.ends_with(".md") {
+            return None;
+        }
+
+        let chapter_dir = self.chapter_path.parent().unwrap_or_else(|| Path::new(""));
+        let book_path = match normalize_path(&chapter_dir.join(path)) {
+            Some(book_path) => self.find_chapter(book_path),
+            None => {
+                log::warn!(
+                    "Diagram link '{}' in chapter '{}' points outside the book.",
+                    target,
+                    self.chapter_path.display()
+                );
+                return None;
+            }
+        };
+
+        let html_path = book_path.with_extension("html");
+        let url = html_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        // The images are stored in a dir in the root of the book
+        Some(format!("../{}{}", url, suffix))
+    }
+
+    /// Find the chapter for the given book path, warns when the chapter does not
+    /// exist. README.md files are converted to index.md by mdbook (when the
+    /// index preprocessor is active), so index.md is tried for these too.
+    fn find_chapter(&self, book_path: PathBuf) -> PathBuf {
+        if self.chapters.contains(&book_path) {
+            return book_path;
+        }
+
+        let is_readme = book_path
+            .file_name()
+            .map_or(false, |name| name.eq_ignore_ascii_case("README.md"));
+        if is_readme {
+            let index_path = book_path.with_file_name("index.md");
+            if self.chapters.contains(&index_path) {
+                return index_path;
+            }
+        }
+
+        log::warn!(
+            "Diagram link '{}' in chapter '{}' does not refer to a chapter in the book.",
+            book_path.display(),
+            self.chapter_path.display()
+        );
+        book_path
+    }
+}
+
+/// Resolve the `.` and `..` components of a relative path, returns None when
+/// the path points outside its root.
+fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => normalized.push(part),
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn get_chapters() -> HashSet<PathBuf> {
+        [
+            "intro.md",
+            "guide/index.md",
+            "guide/intro.md",
+            "design/storage.md",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect()
+    }
+
+    #[test]
+    fn rewrites_chapter_links() {
+        let chapters = get_chapters();
+        let rewriter = ChapterLinkRewriter::new(Path::new("guide/intro.md"), &chapters);
+
+        assert_eq!(
+            "class A [[../design/storage.html]]",
+            rewriter.rewrite("class A [[../design/storage.md]]")
+        );
+        assert_eq!(
+            "A -> B : [[../guide/intro.html#usage{Usage} usage]]",
+            rewriter.rewrite("A -> B : [[intro.md#usage{Usage} usage]]")
+        );
+        assert_eq!(
+            "[[../intro.html]] [[../guide/index.html]]",
+            rewriter.rewrite("[[../intro.md]] [[./README.md]]")
+        );
+
+        let rewriter = ChapterLinkRewriter::new(Path::new("intro.md"), &chapters);
+        assert_eq!(
+            "class A [[../design/storage.html]]",
+            rewriter.rewrite("class A [[design/storage.md]]")
+        );
+    }
+
+    #[test]
+    fn leaves_other_links_alone() {
+        let chapters = get_chapters();
+        let rewriter = ChapterLinkRewriter::new(Path::new("guide/intro.md"), &chapters);
+
+        for code in &[
+            "class A [[https://example.com/foo.md]]",
+            "class A [[/foo.md]]",
+            "class A [[foo.html]]",
+            "class A [[../../outside.md]]",
+            "class A [[{tooltip only}]]",
+            "no links at all",
+            "unterminated [[foo.txt",
+        ] {
+            assert_eq!(*code, rewriter.rewrite(code));
+        }
+    }
+
+    #[test]
+    fn rewrites_broken_links() {
+        let chapters = get_chapters();
+        let rewriter = ChapterLinkRewriter::new(Path::new("guide/intro.md"), &chapters);
+
+        // Warns, but still rewrites the link
+        assert_eq!(
+            "[[../guide/missing.html]]",
+            rewriter.rewrite("[[missing.md]]")
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            Some(PathBuf::from("a/c")),
+            normalize_path(Path::new("a/./b/../c"))
+        );
+        assert_eq!(None, normalize_path(Path::new("a/../../c")));
+        assert_eq!(None, normalize_path(Path::new("/a")));
+    }
+}
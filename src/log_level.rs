@@ -0,0 +1,82 @@
+use crate::get_plantuml_config;
+use log::LevelFilter;
+use std::env;
+use std::str::FromStr;
+
+/// The name of the crate as used in log targets (and log filter directives)
+const LOG_TARGET: &str = "mdbook_plantuml";
+
+/// Get the log level from the `MDBOOK_LOG` or `RUST_LOG` environment variable
+/// (in that order), or None if neither sets a level for the preprocessor.
+pub fn get_env_log_level() -> Option<LevelFilter> {
+    ["MDBOOK_LOG", "RUST_LOG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find_map(|filter| parse_log_filter(&filter))
+}
+
+/// Get the log level configured with the `log-level` option in book.toml
+pub fn get_config_log_level(config: &mdbook::Config) -> Option<LevelFilter> {
+    let level = get_plantuml_config(config).log_level?;
+    LevelFilter::from_str(&level)
+        .map_err(|_| log::warn!("Invalid log-level '{}' in book.toml.", level))
+        .ok()
+}
+
+/// Get the preprocessor's log level from an env_logger style filter (e.g.
+/// `debug` or `mdbook=info,mdbook_plantuml=trace`). A directive for this crate
+/// takes precedence over a global level, directives for other crates are
+/// ignored.
+pub fn parse_log_filter(filter: &str) -> Option<LevelFilter> {
+    let mut global_level = None;
+    for directive in filter.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some((target, level)) => {
+                if target == LOG_TARGET || target.starts_with(&format!("{}::", LOG_TARGET)) {
+                    return LevelFilter::from_str(level).ok();
+                }
+            }
+            None => {
+                if let Ok(level) = LevelFilter::from_str(directive) {
+                    global_level = Some(level);
+                }
+            }
+        }
+    }
+
+    global_level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_log_filter() {
+        assert_eq!(Some(LevelFilter::Debug), parse_log_filter("debug"));
+        assert_eq!(Some(LevelFilter::Info), parse_log_filter("INFO"));
+        assert_eq!(
+            Some(LevelFilter::Trace),
+            parse_log_filter("warn,mdbook_plantuml=trace,mdbook=info")
+        );
+        assert_eq!(
+            Some(LevelFilter::Error),
+            parse_log_filter("mdbook_plantuml::plantuml_renderer=error")
+        );
+        assert_eq!(None, parse_log_filter("mdbook=debug"));
+        assert_eq!(None, parse_log_filter("verbose"));
+    }
+
+    #[test]
+    fn test_get_config_log_level() {
+        let config =
+            mdbook::Config::from_str("[preprocessor.plantuml]\nlog-level = \"info\"\n").unwrap();
+        assert_eq!(Some(LevelFilter::Info), get_config_log_level(&config));
+
+        let config =
+            mdbook::Config::from_str("[preprocessor.plantuml]\nlog-level = \"loud\"\n").unwrap();
+        assert_eq!(None, get_config_log_level(&config));
+        assert_eq!(None, get_config_log_level(&mdbook::Config::default()));
+    }
+}
@@ -1,212 +1,297 @@
-use crate::plantuml_renderer::PlantUMLRendererTrait;
+use crate::figures::{Figure, FigureIndex};
+use crate::link_rewriter::ChapterLinkRewriter;
+use crate::plantuml_renderer::{DiagramAttributes, PlantUMLRendererTrait};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use std::cell::RefCell;
+use std::ops::Range;
 use std::string::String;
 
 pub fn render_plantuml_code_blocks(
     markdown: &str,
     renderer: &impl PlantUMLRendererTrait,
     rel_image_url: &str,
+    link_rewriter: &ChapterLinkRewriter,
+    figures: &RefCell<FigureIndex>,
 ) -> String {
-    let processor = PlantUMLCodeProcessor::new(markdown);
+    let processor = PlantUMLCodeProcessor::new(markdown)
+        .with_link_rewriter(link_rewriter)
+        .with_figures(figures);
     processor.process(renderer, rel_image_url)
 }
 
-/// Find the first byte not equal to the expected byte
-/// Only works for ASCII bytes (in this context ' ', '~' and '`' ), which should
-/// work fine for unicode stuff too.
-/// # Arguments
-/// * `bytes` - The bytes array to parse
-/// * `expected` - The char to compare against (as a byte value)
-/// * `start` - The start offset for the search
-const fn find_first_inequal(bytes: &[u8], expected: u8, start: usize) -> usize {
-    let mut pos = start;
+/// Create a markdown parser with the same extensions as mdbook uses, so code
+/// blocks are detected exactly like mdbook does.
+fn create_parser(markdown: &str) -> Parser<'_, '_> {
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_TABLES);
+    opts.insert(Options::ENABLE_FOOTNOTES);
+    opts.insert(Options::ENABLE_STRIKETHROUGH);
+    opts.insert(Options::ENABLE_TASKLISTS);
 
-    while pos < bytes.len() && bytes[pos] == expected {
-        pos += 1;
-    }
-
-    pos
+    Parser::new_ext(markdown, opts)
 }
 
-/// Find the next line in the given byte array
-/// # Arguments
-/// * `bytes` - The bytes array to parse
-/// * `start` - The start offset for the search
-const fn next_line(bytes: &[u8], start: usize) -> usize {
-    let mut pos = start;
-    while pos < bytes.len() && bytes[pos] != b'\n' {
-        pos += 1;
-    }
-
-    pos + 1
+/// Get the byte ranges of the code blocks and inline code spans in `markdown`
+pub fn get_code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    create_parser(markdown)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect()
 }
 
-/// Find the next code fence (start, or end fence) in the given byte array
-/// # Arguments
-/// * `bytes` - The bytes array to parse
-/// * `start` - The start offset for the search
-/// * `min_length` - Optional length of the code fence to find (used for finding
-///   the closing fence)
-/// * `fence_char` - Optional fence char to match (used for finding the closing
-///   fence)
-fn find_next_code_fence(
-    bytes: &[u8],
-    start: usize,
-    min_length: Option<usize>,
-    fence_char: Option<u8>,
-) -> Option<(usize, usize)> {
-    if bytes.len() < 3 {
-        return None;
-    }
-
-    let mut pos: usize = start;
-
-    let is_fence_char = |c| {
-        // TODO: there is probably a more optimal way of doing this
-        if let Some(expected) = fence_char {
-            expected == c
-        } else {
-            c == b'`' || c == b'~'
-        }
-    };
+/// Get the indentation to use for continuation lines of a block starting at
+/// byte offset `pos`. This is the text between the start of the line and `pos`
+/// with list markers replaced by spaces, so block quote markers and list
+/// indentation are preserved.
+fn get_continuation_indent(markdown: &str, pos: usize) -> String {
+    let line_start = markdown[..pos].rfind('\n').map_or(0, |p| p + 1);
+    markdown[line_start..pos]
+        .chars()
+        .map(|c| {
+            if c == '>' || c.is_whitespace() {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect()
+}
 
-    let is_fence = |s, e| {
-        if let Some(l) = min_length {
-            (e - s) >= l
-        } else {
-            (e - s) >= 3
-        }
-    };
+/// Get the (one based) line number of byte offset `pos` in `markdown`
+fn get_line(markdown: &str, pos: usize) -> usize {
+    markdown[..pos].matches('\n').count() + 1
+}
 
-    while pos < bytes.len() {
-        let line_start = pos;
-        pos = find_first_inequal(bytes, b' ', pos);
+/// Indent all but the first line of `text` with `indent`. Empty lines only get
+/// the non whitespace part of the indent (i.e. block quote markers), so no
+/// trailing whitespace is introduced. This includes the line following a
+/// trailing newline, which is completed by the markdown after the code block.
+fn indent_continuation_lines(text: &str, indent: &str) -> String {
+    if indent.is_empty() {
+        return String::from(text);
+    }
 
-        if (pos - line_start) < 4 && is_fence_char(bytes[pos]) {
-            let first_non_fence = find_first_inequal(bytes, bytes[pos], pos);
-            if is_fence(pos, first_non_fence) {
-                return Some((pos, first_non_fence));
+    let mut indented = String::with_capacity(text.len());
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if i > 0 {
+            if line.trim().is_empty() {
+                indented.push_str(indent.trim_end());
+            } else {
+                indented.push_str(indent);
             }
-
-            pos = first_non_fence;
         }
-
-        pos = next_line(bytes, pos);
+        indented.push_str(line);
+    }
+    if text.ends_with('\n') {
+        indented.push_str(indent.trim_end());
     }
 
-    None
+    indented
 }
 
-/// Gets the code block's info string, or None if it cannot be found.
-/// # Arguments
-/// * `bytes` - The bytes array to parse
-/// * `fence_end` - The start offset for the search
-/// * `min_length` - Optional length of the code fence to find (used for finding
-///   the closing)
-fn get_info_string(bytes: &[u8], fence_end: usize) -> Option<&str> {
-    let info_start = find_first_inequal(bytes, b' ', fence_end);
-    if info_start < bytes.len() {
-        let mut pos = info_start;
-        while pos < bytes.len() && bytes[pos] != b'\n' && bytes[pos] != b' ' && bytes[pos] != b'\r'
-        {
-            pos += 1;
-        }
-
-        if pos > info_start {
-            if let Ok(info) = std::str::from_utf8(&bytes[info_start..pos]) {
-                return Some(info);
+/// Split an info string at the commas outside of double quoted values
+fn split_info_string(info_string: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in info_string.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(&info_string[start..i]);
+                start = i + 1;
             }
+            _ => {}
         }
     }
+    parts.push(&info_string[start..]);
+
+    parts
+}
+
+/// A PlantUML code block of a markdown document
+pub struct Diagram {
+    /// The diagram source
+    pub code: String,
+    /// The (one based) line number of the first line of the diagram source in
+    /// the markdown document
+    pub line: usize,
+}
 
-    None
+/// Get all the PlantUML code blocks of a markdown document, in document order
+pub fn get_diagrams(markdown: &str) -> Vec<Diagram> {
+    PlantUMLCodeProcessor::new(markdown).get_diagrams()
 }
 
-struct CodeBlock<'a> {
-    /// The code block's code slice (stripped from fences and info string)
-    code: &'a str,
+struct CodeBlock {
+    /// The code block's code (stripped from fences, info string and container
+    /// prefixes like block quote markers and list indentation)
+    code: String,
     /// The code block's info string (if any)
-    info_string: Option<&'a str>,
+    info_string: Option<String>,
     /// Byte offset of first character of opening fence
     start_pos: usize,
     /// Byte offset of newline after closing fence
     end_pos: usize,
 }
 
-impl<'a> CodeBlock<'a> {
+impl CodeBlock {
     /// Returns true if this code block is plantuml (i.e. starts with plantuml or puml)
     fn is_plantuml(&self) -> bool {
-        let language = self.info_string.and_then(|info| info.split(',').next());
+        let language = self
+            .info_string
+            .as_deref()
+            .and_then(|info| info.split(|c: char| c == ',' || c.is_whitespace()).next());
         language == Some("plantuml") || language == Some("puml")
     }
 
+    /// Get the (non empty) value of a `key=value` pair in the info string, or
+    /// None if the key is not present. Values containing spaces or commas can
+    /// be double quoted (e.g. `caption="Storage, overview"`).
+    fn get_attribute(&self, key: &str) -> Option<&str> {
+        let parts = split_info_string(self.info_string.as_deref().unwrap_or(""));
+        for part in parts {
+            if let Some((name, value)) = part.trim().split_once('=') {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                if name == key && !value.is_empty() {
+                    return Some(value);
+                }
+            }
+        }
+
+        None
+    }
+
     fn get_format(&self) -> String {
         if self.code.contains("@startditaa") {
             String::from("png")
         } else {
-            let parts = self.info_string.unwrap_or("").split(',');
-            for part in parts {
-                let eq_char = part.find('=').unwrap_or(part.len());
-
-                if part[0..eq_char] == *"format" && part.len() > eq_char + 1 {
-                    return String::from(&part[eq_char + 1..part.len()]);
-                }
-            }
+            String::from(self.get_attribute("format").unwrap_or("svg"))
+        }
+    }
 
-            String::from("svg")
+    fn get_diagram_attributes(&self) -> DiagramAttributes {
+        let get = |key| self.get_attribute(key).map(String::from);
+        DiagramAttributes {
+            width: get("width"),
+            height: get("height"),
+            scale: get("scale"),
+            align: get("align"),
+            class: get("class"),
+            id: get("id"),
+            caption: get("caption"),
+            ..DiagramAttributes::default()
         }
     }
 }
 
 struct PlantUMLCodeProcessor<'a> {
     markdown: &'a str,
+    link_rewriter: Option<&'a ChapterLinkRewriter<'a>>,
+    figures: Option<&'a RefCell<FigureIndex>>,
 }
 
 impl<'a> PlantUMLCodeProcessor<'a> {
-    pub const fn new(markdown: &str) -> PlantUMLCodeProcessor {
-        PlantUMLCodeProcessor { markdown }
+    pub const fn new(markdown: &str) -> PlantUMLCodeProcessor<'_> {
+        PlantUMLCodeProcessor {
+            markdown,
+            link_rewriter: None,
+            figures: None,
+        }
     }
 
-    /// Returns the byte offsets of the (optional) end fence and code end
-    /// positions as a tuple.
-    /// Returns bytes.len() for both if the end fence is None
-    /// # Arguments
-    /// * `bytes` - The bytes array to parse
-    /// * `fence_end` - Option with the byte offsets of the end fence
-    const fn get_end_positions(bytes: &[u8], fence_end: Option<(usize, usize)>) -> (usize, usize) {
-        if let Some((code_end, fe)) = fence_end {
-            let end_pos = {
-                let p = next_line(bytes, fe);
-                if p == bytes.len() {
-                    p
-                } else {
-                    p - 1
+    /// Rewrite the chapter links in the diagrams before rendering them
+    pub const fn with_link_rewriter(
+        mut self,
+        link_rewriter: &'a ChapterLinkRewriter<'a>,
+    ) -> PlantUMLCodeProcessor<'a> {
+        self.link_rewriter = Some(link_rewriter);
+        self
+    }
+
+    /// Number the diagrams with an id as figures of the current chapter
+    pub const fn with_figures(
+        mut self,
+        figures: &'a RefCell<FigureIndex>,
+    ) -> PlantUMLCodeProcessor<'a> {
+        self.figures = Some(figures);
+        self
+    }
+
+    /// Number the diagram as a figure if it has an id or caption (returns
+    /// None if the diagram is not a figure). Errors (i.e. duplicate ids) are
+    /// recorded in the figure index.
+    fn get_figure(
+        &self,
+        code: &str,
+        image_format: &str,
+        attributes: &DiagramAttributes,
+    ) -> Option<Figure> {
+        if attributes.id.is_none() && attributes.caption.is_none() {
+            return None;
+        }
+
+        let figures = self.figures?;
+        let figure = figures
+            .borrow_mut()
+            .add_figure(code, image_format, attributes);
+        figure
+            .map_err(|e| figures.borrow_mut().add_error(e.to_string()))
+            .ok()
+    }
+
+    /// Get all fenced code blocks in the document, in document order.
+    fn get_code_blocks(&self) -> Vec<CodeBlock> {
+        let mut code_blocks = Vec::new();
+        let mut current: Option<CodeBlock> = None;
+
+        for (event, range) in create_parser(self.markdown).into_offset_iter() {
+            match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                    let info_string = Some(info.trim())
+                        .filter(|info| !info.is_empty())
+                        .map(String::from);
+                    current = Some(CodeBlock {
+                        code: String::new(),
+                        info_string,
+                        start_pos: range.start,
+                        end_pos: range.end,
+                    });
                 }
-            };
-            (code_end, end_pos)
-        } else {
-            (bytes.len(), bytes.len())
+                Event::Text(text) => {
+                    if let Some(code_block) = current.as_mut() {
+                        code_block.code.push_str(&text);
+                    }
+                }
+                Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
+                    if let Some(code_block) = current.take() {
+                        code_blocks.push(code_block);
+                    }
+                }
+                _ => {}
+            }
         }
+
+        code_blocks
     }
 
-    /// Get next code block in document, starting at byte offset start_pos
-    /// Returns None if no more code blocks are found.
-    fn get_next_code_block(&self, start_pos: usize) -> Option<CodeBlock> {
-        let bytes = self.markdown.as_bytes();
-        if let Some((s, e)) = find_next_code_fence(bytes, start_pos, None, None) {
-            let info_string = get_info_string(bytes, e);
-            let code_start = next_line(bytes, e);
-            let fence_end = find_next_code_fence(bytes, e, Some(e - s), Some(bytes[s]));
-            let (code_end, end_pos) = Self::get_end_positions(bytes, fence_end);
-
-            Some(CodeBlock {
-                code: &self.markdown[code_start..code_end],
-                info_string,
-                start_pos: s,
-                end_pos,
+    /// Get the PlantUML code blocks in the document (without rendering them)
+    fn get_diagrams(&self) -> Vec<Diagram> {
+        self.get_code_blocks()
+            .into_iter()
+            .filter(CodeBlock::is_plantuml)
+            .map(|code_block| Diagram {
+                // The code starts on the line after the opening fence
+                line: get_line(self.markdown, code_block.start_pos) + 1,
+                code: code_block.code,
             })
-        } else {
-            None
-        }
+            .collect()
     }
 
     /// Processes all code blocks in the document (self.markdown)
@@ -220,23 +305,38 @@ impl<'a> PlantUMLCodeProcessor<'a> {
         let mut processed = String::new();
         processed.reserve(self.markdown.len());
 
-        let bytes = self.markdown.as_bytes();
         let mut start_pos: usize = 0;
-        while start_pos < bytes.len() {
-            if let Some(code_block) = self.get_next_code_block(start_pos) {
-                if code_block.is_plantuml() {
-                    processed.push_str(&self.markdown[start_pos..code_block.start_pos]);
-                    let format = code_block.get_format();
-
-                    let rendered = renderer.render(code_block.code, rel_image_url, format);
-                    processed.push_str(rendered.as_str());
-                } else {
-                    processed.push_str(&self.markdown[start_pos..code_block.end_pos]);
-                }
+        let mut has_diagrams = false;
+        for code_block in self.get_code_blocks() {
+            if code_block.is_plantuml() {
+                has_diagrams = true;
+                processed.push_str(&self.markdown[start_pos..code_block.start_pos]);
+                let format = code_block.get_format();
+
+                let code = match self.link_rewriter {
+                    Some(link_rewriter) => link_rewriter.rewrite(&code_block.code),
+                    None => code_block.code.clone(),
+                };
+                let mut attributes = code_block.get_diagram_attributes();
+                attributes.line = Some(get_line(self.markdown, code_block.start_pos));
+                attributes.figure = self.get_figure(&code, &format, &attributes);
+                let mut rendered = renderer.render(&code, rel_image_url, format, &attributes);
+                rendered.push_str(&renderer.render_source(&code_block.code));
+
+                // Keep the rendered output inside its container (list item,
+                // block quote) by indenting it like the code block was
+                let indent = get_continuation_indent(self.markdown, code_block.start_pos);
+                processed.push_str(&indent_continuation_lines(&rendered, &indent));
                 start_pos = code_block.end_pos;
-            } else {
-                processed.push_str(&self.markdown[start_pos..]);
-                start_pos = bytes.len();
+            }
+        }
+        processed.push_str(&self.markdown[start_pos..]);
+
+        if has_diagrams {
+            let assets = renderer.get_chapter_assets(rel_image_url);
+            if !assets.is_empty() {
+                processed.push_str("\n\n");
+                processed.push_str(&assets);
             }
         }
 
@@ -256,108 +356,18 @@ mod test {
     }
 
     impl PlantUMLRendererTrait for FakeRenderer {
-        fn render(&self, code_block: &str, _rel_image_url: &str, _image_format: String) -> String {
+        fn render(
+            &self,
+            code_block: &str,
+            _rel_image_url: &str,
+            _image_format: String,
+            _attributes: &DiagramAttributes,
+        ) -> String {
             self.code_block.replace(code_block.to_string());
             String::from("rendered")
         }
     }
 
-    #[test]
-    fn test_find_next_code_fence() {
-        macro_rules! assert_find_next_code_fence {
-            ($expected_slice_opt:expr, $markdown:expr, $start:expr, $min_length: expr, $fence_char: expr) => {{
-                let fence_range = find_next_code_fence($markdown, $start, $min_length, $fence_char);
-                if let Some((s, e)) = $expected_slice_opt {
-                    assert!(fence_range.is_some());
-                    assert_eq!((s, e), fence_range.unwrap());
-                } else {
-                    assert!(fence_range.is_none());
-                }
-            }};
-        }
-
-        assert_find_next_code_fence!(None, b"", 0, None, None);
-        assert_find_next_code_fence!(None, b"a\n\n", 0, None, None);
-        assert_find_next_code_fence!(None, b"a```", 0, None, None);
-
-        // Only spaces before the fence chars, _nothing_ else
-        assert_find_next_code_fence!(None, b"\\ ```", 0, None, None);
-
-        // At least 3 chars
-        assert_find_next_code_fence!(None, b"``", 0, None, None);
-        assert_find_next_code_fence!(Some((0, 3)), b"```", 0, None, None);
-        assert_find_next_code_fence!(Some((0, 4)), b"````", 0, None, None);
-        assert_find_next_code_fence!(Some((0, 5)), b"`````", 0, None, None);
-        assert_find_next_code_fence!(None, b"~~", 0, None, None);
-        assert_find_next_code_fence!(Some((0, 3)), b"~~~", 0, None, None);
-        assert_find_next_code_fence!(Some((0, 4)), b"~~~~", 0, None, None);
-        assert_find_next_code_fence!(Some((0, 5)), b"~~~~~", 0, None, None);
-
-        // At most 3 spaces indent
-        assert_find_next_code_fence!(Some((1, 4)), b" ```", 0, None, None);
-        assert_find_next_code_fence!(Some((2, 5)), b"  ```", 0, None, None);
-        assert_find_next_code_fence!(Some((3, 6)), b"   ```", 0, None, None);
-        assert_find_next_code_fence!(None, b"    ```", 0, None, None);
-
-        // Somewhere further in the document
-        assert_find_next_code_fence!(Some((4, 7)), b"abc\n~~~\n", 0, None, None);
-        assert_find_next_code_fence!(Some((10, 14)), b"abc\n~~\n\n  ````\n", 0, None, None);
-
-        // Somewhere further in the document with windows line endings
-        assert_find_next_code_fence!(Some((5, 8)), b"abc\r\n~~~\r\n", 0, None, None);
-        assert_find_next_code_fence!(
-            Some((13, 17)),
-            b"abc\r\n~~\r\n\r\n  ````\r\n",
-            0,
-            None,
-            None
-        );
-
-        // Find specific min length
-        assert_find_next_code_fence!(Some((4, 8)), b"```\n````", 0, Some(4), None);
-        assert_find_next_code_fence!(Some((4, 10)), b"```\n``````", 0, Some(4), None);
-
-        // Start offset
-        assert_find_next_code_fence!(Some((5, 8)), b"```  ```", 3, None, None);
-        assert_find_next_code_fence!(Some((8, 11)), b"```\n~~~\n```", 3, Some(3), Some(b'`'));
-
-        // Rest
-        assert_find_next_code_fence!(Some((0, 3)), b"``` ```", 0, None, None);
-        assert_find_next_code_fence!(None, b"``~~~", 0, None, None);
-    }
-
-    #[test]
-    fn test_get_info_string() {
-        #![allow(clippy::string_lit_as_bytes)]
-        macro_rules! assert_get_info_string {
-            ($markdown:expr, $start:expr, $expected_range: expr) => {{
-                let bytes = $markdown.as_bytes();
-
-                let slice = get_info_string(bytes, $start);
-                if let Some((s, e)) = $expected_range {
-                    assert_eq!(Some(&$markdown[s..e]), slice);
-                } else {
-                    assert!(slice.is_none());
-                }
-            }};
-        }
-
-        assert_get_info_string!("", 0, None);
-        assert_get_info_string!("  ", 0, None);
-        assert_get_info_string!("\n", 0, None);
-
-        assert_get_info_string!("foobar", 0, Some((0, 6)));
-        assert_get_info_string!("foobar\n", 0, Some((0, 6)));
-        assert_get_info_string!("foobar\r\n", 0, Some((0, 6)));
-        assert_get_info_string!("foobar ", 0, Some((0, 6)));
-
-        assert_get_info_string!("foobar baz", 0, Some((0, 6)));
-        assert_get_info_string!("  foobar  \n", 0, Some((2, 8)));
-        assert_get_info_string!("  foobar baz \n", 0, Some((2, 8)));
-
-        assert_get_info_string!("some```foobar", 7, Some((7, 13)));
-    }
-
     #[test]
     fn test_process_plantuml_code() {
         macro_rules! assert_plantuml_injection {
@@ -417,13 +427,241 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_process_nested_plantuml_code() {
+        macro_rules! assert_plantuml_injection {
+            ($markdown:expr, $expected_code_block:expr, $rendered_output:expr) => {{
+                let processor = PlantUMLCodeProcessor::new($markdown);
+                let renderer = FakeRenderer {
+                    code_block: RefCell::new(String::new()),
+                };
+                let result = processor.process(&renderer, &String::default());
+                assert_eq!($expected_code_block, *renderer.code_block.borrow());
+                assert_eq!($rendered_output, result);
+            }};
+        }
+
+        // Block quotes
+        assert_plantuml_injection!(
+            "> abc\n> ```plantuml\n> foo\n> bar\n> ```\n> def",
+            "foo\nbar\n",
+            "> abc\n> rendered\n> def"
+        );
+
+        // List items (indented by more than 3 spaces)
+        assert_plantuml_injection!(
+            "1. abc\n\n    ```plantuml\n    foo\n      bar\n    ```\n2. def",
+            "foo\n  bar\n",
+            "1. abc\n\n    rendered\n2. def"
+        );
+
+        // Tab indentation
+        assert_plantuml_injection!(
+            "- abc\n\t```puml\n\tfoo\n\t```\n",
+            "foo\n",
+            "- abc\n\trendered\n"
+        );
+
+        // Info string with trailing text
+        assert_plantuml_injection!("```plantuml foo\nbar\n```", "bar\n", "rendered");
+
+        // Fences inside other code blocks are not code blocks
+        assert_plantuml_injection!(
+            "````\n```plantuml\nfoo\n```\n````",
+            "",
+            "````\n```plantuml\nfoo\n```\n````"
+        );
+        assert_plantuml_injection!(
+            "    ```plantuml\n    foo\n    ```",
+            "",
+            "    ```plantuml\n    foo\n    ```"
+        );
+
+        // Windows line endings around the code block are preserved
+        assert_plantuml_injection!(
+            "abc\r\n~~~plantuml\r\nfoo\r\n~~~\r\ndef\r\n",
+            "foo\n",
+            "abc\r\nrendered\r\ndef\r\n"
+        );
+    }
+
+    struct MultiLineRenderer;
+
+    impl PlantUMLRendererTrait for MultiLineRenderer {
+        fn render(
+            &self,
+            _code_block: &str,
+            _rel_image_url: &str,
+            _image_format: String,
+            _attributes: &DiagramAttributes,
+        ) -> String {
+            String::from("![](img1.svg)\n\n![](img2.svg)\n\n")
+        }
+    }
+
+    #[test]
+    fn test_process_indents_rendered_output() {
+        macro_rules! assert_indented {
+            ($markdown:expr, $rendered_output:expr) => {{
+                let processor = PlantUMLCodeProcessor::new($markdown);
+                assert_eq!(
+                    $rendered_output,
+                    processor.process(&MultiLineRenderer {}, &String::default())
+                );
+            }};
+        }
+
+        assert_indented!(
+            "```plantuml\nfoo\n```\nabc",
+            "![](img1.svg)\n\n![](img2.svg)\n\n\nabc"
+        );
+        assert_indented!(
+            "1. abc\n\n   ```plantuml\n   foo\n   ```\n2. def",
+            "1. abc\n\n   ![](img1.svg)\n\n   ![](img2.svg)\n\n\n2. def"
+        );
+        assert_indented!(
+            "- ```plantuml\n  foo\n  ```\n- def",
+            "- ![](img1.svg)\n\n  ![](img2.svg)\n\n\n- def"
+        );
+        assert_indented!(
+            "> 1. ```plantuml\n>    foo\n>    ```\n> def",
+            "> 1. ![](img1.svg)\n>\n>    ![](img2.svg)\n>\n>\n> def"
+        );
+        assert_indented!(
+            "- abc\n\n\t```puml\n\tfoo\n\t```\n",
+            "- abc\n\n\t![](img1.svg)\n\n\t![](img2.svg)\n\n\n"
+        );
+    }
+
+    struct AssetsRenderer;
+
+    impl PlantUMLRendererTrait for AssetsRenderer {
+        fn render(
+            &self,
+            _code_block: &str,
+            _rel_image_url: &str,
+            _image_format: String,
+            _attributes: &DiagramAttributes,
+        ) -> String {
+            String::from("rendered")
+        }
+
+        fn get_chapter_assets(&self, rel_image_url: &str) -> String {
+            format!("<link href=\"{}/assets.css\">\n", rel_image_url)
+        }
+    }
+
+    #[test]
+    fn test_process_adds_chapter_assets() {
+        let processor =
+            PlantUMLCodeProcessor::new("```plantuml\nfoo\n```\n```plantuml\nbar\n```\n");
+        assert_eq!(
+            "rendered\nrendered\n\n\n<link href=\"img/assets.css\">\n",
+            processor.process(&AssetsRenderer {}, "img")
+        );
+
+        // Only chapters with diagrams get the assets
+        let processor = PlantUMLCodeProcessor::new("```c++\nfoo\n```\n");
+        assert_eq!(
+            "```c++\nfoo\n```\n",
+            processor.process(&AssetsRenderer {}, "img")
+        );
+    }
+
+    struct SourceRenderer;
+
+    impl PlantUMLRendererTrait for SourceRenderer {
+        fn render(
+            &self,
+            code_block: &str,
+            _rel_image_url: &str,
+            _image_format: String,
+            _attributes: &DiagramAttributes,
+        ) -> String {
+            format!("rendered {}", code_block)
+        }
+
+        fn render_source(&self, plantuml_code: &str) -> String {
+            format!("source {}", plantuml_code)
+        }
+    }
+
+    #[test]
+    fn test_process_adds_original_source() {
+        let chapters = std::collections::HashSet::new();
+        let link_rewriter = ChapterLinkRewriter::new(std::path::Path::new("chapter.md"), &chapters);
+        let processor = PlantUMLCodeProcessor::new("```plantuml\n[[foo.md]]\n```\n")
+            .with_link_rewriter(&link_rewriter);
+        assert_eq!(
+            "rendered [[../foo.html]]\nsource [[foo.md]]\n\n",
+            processor.process(&SourceRenderer {}, "img")
+        );
+    }
+
+    struct FigureRenderer;
+
+    impl PlantUMLRendererTrait for FigureRenderer {
+        fn render(
+            &self,
+            _code_block: &str,
+            _rel_image_url: &str,
+            _image_format: String,
+            attributes: &DiagramAttributes,
+        ) -> String {
+            format!("{:?}", attributes.figure.as_ref().map(|f| &f.label))
+        }
+    }
+
+    #[test]
+    fn test_process_numbers_figures() {
+        let figures = RefCell::new(FigureIndex::new(crate::figures::FigureNumbering::Chapter));
+        figures
+            .borrow_mut()
+            .start_chapter(std::path::Path::new("chapter.md"), Some(&[3]));
+        let processor = PlantUMLCodeProcessor::new(
+            "```plantuml,id=a\n```\n```plantuml\n```\n```plantuml,id=b\n```\n```plantuml,id=a\n```\n",
+        )
+        .with_figures(&figures);
+        assert_eq!(
+            "Some(\"Figure 3.1\")\nNone\nSome(\"Figure 3.2\")\nNone\n",
+            processor.process(&FigureRenderer {}, "img")
+        );
+        // The duplicate id is recorded, so it fails the build
+        assert_eq!(
+            vec![String::from(
+                "Duplicate figure id 'a' in chapter 'chapter.md' (already used in chapter \
+                 'chapter.md')."
+            )],
+            figures.borrow().get_errors()
+        );
+    }
+
+    #[test]
+    fn test_process_strips_fence_indentation_from_code() {
+        let processor = PlantUMLCodeProcessor::new(
+            "1. abc\n\n   ```plantuml\n   @startuml\n     A --|> B\n   @enduml\n   ```\n",
+        );
+        let renderer = FakeRenderer {
+            code_block: RefCell::new(String::new()),
+        };
+        processor.process(&renderer, &String::default());
+        assert_eq!(
+            "@startuml\n  A --|> B\n@enduml\n",
+            *renderer.code_block.borrow()
+        );
+
+        let processor = PlantUMLCodeProcessor::new("  ```plantuml\n  foo\n   bar\n  ```\n");
+        processor.process(&renderer, &String::default());
+        assert_eq!("foo\n bar\n", *renderer.code_block.borrow());
+    }
+
     #[test]
     fn test_codeblock_plantuml_detection() {
         macro_rules! is_plantuml_code_block {
             ($info_str:expr) => {{
                 let code_block = CodeBlock {
-                    code: "Foo",
-                    info_string: Some($info_str),
+                    code: String::from("Foo"),
+                    info_string: Some(String::from($info_str)),
                     start_pos: 0,
                     end_pos: 0,
                 };
@@ -447,8 +685,8 @@ mod test {
             }};
             ($info_str:expr, $code: expr) => {{
                 let code_block = CodeBlock {
-                    code: $code,
-                    info_string: Some($info_str),
+                    code: String::from($code),
+                    info_string: Some(String::from($info_str)),
                     start_pos: 0,
                     end_pos: 0,
                 };
@@ -470,4 +708,65 @@ mod test {
         assert_eq!("svg", get_format!("plantuml,bruh=123,format=,bruh=123"));
         assert_eq!("svg", get_format!("plantuml,bruh=123"));
     }
+
+    #[test]
+    fn test_plantuml_codeblock_attributes() {
+        macro_rules! get_attributes {
+            ($info_str:expr) => {{
+                let code_block = CodeBlock {
+                    code: String::from("foo"),
+                    info_string: Some(String::from($info_str)),
+                    start_pos: 0,
+                    end_pos: 0,
+                };
+
+                code_block.get_diagram_attributes()
+            }};
+        }
+
+        assert_eq!(DiagramAttributes::default(), get_attributes!("plantuml"));
+        assert_eq!(
+            DiagramAttributes {
+                width: Some(String::from("300px")),
+                height: Some(String::from("200")),
+                scale: Some(String::from("1.5")),
+                align: Some(String::from("center")),
+                class: Some(String::from("wide")),
+                ..DiagramAttributes::default()
+            },
+            get_attributes!("plantuml,width=300px,height=200,scale=1.5,align=center,class=wide")
+        );
+        assert_eq!(
+            DiagramAttributes {
+                width: Some(String::from("50%")),
+                ..DiagramAttributes::default()
+            },
+            get_attributes!("plantuml,format=png,width=50%,height=")
+        );
+        assert_eq!(
+            DiagramAttributes {
+                id: Some(String::from("storage-layout")),
+                ..DiagramAttributes::default()
+            },
+            get_attributes!("plantuml,id=storage-layout")
+        );
+        assert_eq!(
+            DiagramAttributes {
+                id: Some(String::from("a")),
+                caption: Some(String::from("Storage, overview")),
+                ..DiagramAttributes::default()
+            },
+            get_attributes!("plantuml,caption=\"Storage, overview\",id=a")
+        );
+    }
+
+    #[test]
+    fn test_get_diagrams() {
+        let markdown = "# Title\n\n```plantuml\nfoo\n```\n\n```rust\nbar\n```\n\n\
+                        > ```puml,format=png\n> baz\n> ```\n";
+        let diagrams = get_diagrams(markdown);
+        assert_eq!(2, diagrams.len());
+        assert_eq!(("foo\n", 4), (diagrams[0].code.as_str(), diagrams[0].line));
+        assert_eq!(("baz\n", 12), (diagrams[1].code.as_str(), diagrams[1].line));
+    }
 }
@@ -0,0 +1,128 @@
+use anyhow::{bail, Context, Result};
+use semver::{Version, VersionReq};
+
+/// How the version of the mdbook calling the preprocessor relates to the
+/// version the preprocessor was built against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compatibility {
+    /// The same, or a newer compatible version
+    Compatible,
+    /// An older compatible version, which may lack features used by the
+    /// preprocessor
+    Drift,
+    /// Another major version (or minor version for 0.x versions)
+    Incompatible,
+}
+
+/// Check the version of the mdbook calling the preprocessor against the
+/// version of mdbook the preprocessor was built against. Compatible versions
+/// are accepted silently, older compatible versions result in a warning (or an
+/// error when `strict`) and incompatible versions in an error.
+pub fn check_mdbook_version(mdbook_version: &str, strict: bool) -> Result<()> {
+    check_version(mdbook_version, mdbook::MDBOOK_VERSION, strict)
+}
+
+fn check_version(mdbook_version: &str, built_version: &str, strict: bool) -> Result<()> {
+    let message = match get_compatibility(mdbook_version, built_version)? {
+        Compatibility::Compatible => return Ok(()),
+        Compatibility::Drift => format!(
+            "The plantuml preprocessor was built against mdbook {}, but is called from \
+             the older mdbook {}. Please upgrade mdbook.",
+            built_version, mdbook_version
+        ),
+        Compatibility::Incompatible => bail!(
+            "The plantuml preprocessor was built against mdbook {}, which is incompatible \
+             with mdbook {}. Please use a version of mdbook-plantuml built for this mdbook \
+             version.",
+            built_version,
+            mdbook_version
+        ),
+    };
+
+    if strict {
+        bail!("{} (--strict-version is set)", message);
+    }
+    log::warn!("{}", message);
+    Ok(())
+}
+
+fn get_compatibility(mdbook_version: &str, built_version: &str) -> Result<Compatibility> {
+    let version = Version::parse(mdbook_version)
+        .with_context(|| format!("Invalid mdbook version '{}'", mdbook_version))?;
+    // A caret requirement matches the versions with the same major version
+    // (or major and minor version for 0.x versions) that are not older
+    let requirement = VersionReq::parse(&format!("^{}", built_version))
+        .with_context(|| format!("Invalid mdbook version '{}'", built_version))?;
+    if requirement.matches(&version) {
+        return Ok(Compatibility::Compatible);
+    }
+
+    let built = Version::parse(built_version)?;
+    let same_major = if built.major == 0 {
+        version.major == 0 && version.minor == built.minor
+    } else {
+        version.major == built.major
+    };
+    Ok(if same_major {
+        Compatibility::Drift
+    } else {
+        Compatibility::Incompatible
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_get_compatibility() {
+        assert_eq!(
+            Compatibility::Compatible,
+            get_compatibility("0.4.21", "0.4.21").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Compatible,
+            get_compatibility("0.4.30", "0.4.21").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Drift,
+            get_compatibility("0.4.17", "0.4.21").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Incompatible,
+            get_compatibility("0.5.0", "0.4.21").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Incompatible,
+            get_compatibility("0.3.7", "0.4.21").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Incompatible,
+            get_compatibility("1.0.0", "0.4.21").unwrap()
+        );
+
+        assert_eq!(
+            Compatibility::Compatible,
+            get_compatibility("1.3.0", "1.2.0").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Drift,
+            get_compatibility("1.1.9", "1.2.0").unwrap()
+        );
+        assert_eq!(
+            Compatibility::Incompatible,
+            get_compatibility("2.0.0", "1.2.0").unwrap()
+        );
+
+        assert!(get_compatibility("latest", "0.4.21").is_err());
+    }
+
+    #[test]
+    fn test_check_version() {
+        assert!(check_version("0.4.21", "0.4.21", true).is_ok());
+        assert!(check_version("0.4.17", "0.4.21", false).is_ok());
+        assert!(check_version("0.4.17", "0.4.21", true).is_err());
+        assert!(check_version("0.5.0", "0.4.21", false).is_err());
+    }
+}
@@ -1,9 +1,17 @@
 use anyhow::Result;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Pseudo image format for rendering the client side image map (cmapx) of a
+/// diagram instead of the image itself. This matches the PlantUML server's
+/// `map` URL path, the shell backend uses PlantUML's `-pipemap` option.
+pub const IMAGE_MAP_FORMAT: &str = "map";
 
 pub trait PlantUMLBackend {
     /// Render a PlantUML string to file and return the diagram URL path to this
     /// file (as a String) for use in a link.
+    /// Diagrams with more than one page (`newpage`, or multiple `@startuml`
+    /// blocks) write the additional pages next to `output_file`, named as
+    /// returned by `get_page_filename`.
     /// # Arguments
     /// * `plantuml_code` - The present source of the code block
     /// * `output_file` - The path to the file to save the image to
@@ -15,4 +23,157 @@ pub trait PlantUMLBackend {
         image_format: &str,
         output_file: &Path,
     ) -> Result<()>;
+
+    /// Check the syntax of a PlantUML diagram without rendering it. Returns
+    /// the syntax error, or None if the diagram is valid.
+    /// # Arguments
+    /// * `plantuml_code` - The present source of the code block
+    fn check_syntax(&self, plantuml_code: &str) -> Result<Option<SyntaxError>>;
+}
+
+/// A syntax error in a PlantUML diagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The (zero based) line of the error in the diagram source, if known
+    pub line: Option<usize>,
+    /// The error message
+    pub message: String,
+}
+
+/// Get the file name of the given page of a (multi page) diagram, using the
+/// same naming scheme as PlantUML (foo.svg, foo_001.svg, foo_002.svg, ...).
+/// Page 0 is the first page (i.e. `first_page` itself).
+/// # Arguments
+/// * `first_page` - The path of the image file of the first page
+/// * `page` - The zero based page index
+pub fn get_page_filename(first_page: &Path, page: usize) -> PathBuf {
+    if page == 0 {
+        return first_page.to_path_buf();
+    }
+
+    let file_name = first_page
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    // Split at the first dot to deal with extensions like .braille.png
+    let page_name = match file_name.find('.') {
+        Some(dot) => format!("{}_{:03}{}", &file_name[..dot], page, &file_name[dot..]),
+        None => format!("{}_{:03}", file_name, page),
+    };
+
+    first_page.with_file_name(page_name)
+}
+
+/// Get the image files of all the pages of a diagram that exist on disk.
+/// Returns an empty vector when the first page does not exist.
+/// # Arguments
+/// * `first_page` - The path of the image file of the first page
+pub fn get_existing_pages(first_page: &Path) -> Vec<PathBuf> {
+    let mut pages = Vec::new();
+    loop {
+        let page = get_page_filename(first_page, pages.len());
+        if !page.exists() {
+            break;
+        }
+        pages.push(page);
+    }
+
+    pages
+}
+
+/// Count the number of pages PlantUML will generate for the given source.
+/// Every `@start...` line starts a new diagram, every `newpage` line adds a page
+/// to the current diagram.
+#[cfg_attr(
+    not(any(feature = "plantuml-ssl-server", feature = "plantuml-server")),
+    allow(dead_code)
+)]
+pub fn count_pages(plantuml_code: &str) -> usize {
+    let pages = plantuml_code
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            line.starts_with("@start")
+                || *line == "newpage"
+                || line.starts_with("newpage ")
+                || line.starts_with("newpage\t")
+        })
+        .count();
+
+    pages.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_get_page_filename() {
+        assert_eq!(
+            PathBuf::from("foo/bar.svg"),
+            get_page_filename(Path::new("foo/bar.svg"), 0)
+        );
+        assert_eq!(
+            PathBuf::from("foo/bar_001.svg"),
+            get_page_filename(Path::new("foo/bar.svg"), 1)
+        );
+        assert_eq!(
+            PathBuf::from("foo/bar_012.braille.png"),
+            get_page_filename(Path::new("foo/bar.braille.png"), 12)
+        );
+        assert_eq!(
+            PathBuf::from("bar_002"),
+            get_page_filename(Path::new("bar"), 2)
+        );
+    }
+
+    #[test]
+    fn test_get_existing_pages() {
+        let dir = tempdir().unwrap();
+        let first_page = dir.path().join("foo.svg");
+        assert!(get_existing_pages(&first_page).is_empty());
+
+        fs_write(&first_page);
+        assert_eq!(vec![first_page.clone()], get_existing_pages(&first_page));
+
+        fs_write(&dir.path().join("foo_001.svg"));
+        fs_write(&dir.path().join("foo_002.svg"));
+        // Not sequential, so not part of the page set
+        fs_write(&dir.path().join("foo_004.svg"));
+        assert_eq!(
+            vec![
+                first_page.clone(),
+                dir.path().join("foo_001.svg"),
+                dir.path().join("foo_002.svg")
+            ],
+            get_existing_pages(&first_page)
+        );
+    }
+
+    fn fs_write(path: &Path) {
+        std::fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn test_count_pages() {
+        assert_eq!(1, count_pages(""));
+        assert_eq!(1, count_pages("A --|> B"));
+        assert_eq!(1, count_pages("@startuml\nA --|> B\n@enduml"));
+        assert_eq!(
+            2,
+            count_pages("@startuml\nA --|> B\nnewpage\nB --|> C\n@enduml")
+        );
+        assert_eq!(
+            3,
+            count_pages(
+                "@startuml\nA --|> B\n  newpage Title\nB --|> C\n@enduml\n\
+                 @startuml\nC --|> D\n@enduml\n"
+            )
+        );
+        // Not a newpage directive
+        assert_eq!(1, count_pages("@startuml\nnewpages --|> B\n@enduml"));
+    }
 }
@@ -6,11 +6,41 @@ use crate::plantumlconfig::PlantUMLConfig;
 #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
 use reqwest::Url;
 
+/// The PlantUML backend selected by the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Render using the given PlantUML shell command
+    Shell(String),
+    /// Render using the PlantUML server at the given URL
+    #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+    Server(Url),
+}
+
+impl BackendKind {
+    /// The name of the backend kind (as used in the build report)
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Shell(_) => "shell",
+            #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+            Self::Server(_) => "server",
+        }
+    }
+}
+
 /// Create an instance of the PlantUMLBackend
 /// # Arguments
-/// * `img_root` - The path to the directory where to store the images
 /// * `cfg` - The configuration options
 pub fn create(cfg: &PlantUMLConfig) -> Box<dyn PlantUMLBackend> {
+    match get_backend_kind(cfg) {
+        BackendKind::Shell(cmd) => Box::new(PlantUMLShell::new(cmd)),
+        #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+        BackendKind::Server(server_url) => Box::new(PlantUMLServer::new(server_url)),
+    }
+}
+
+/// Get the backend to use for the configured PlantUML command, a server is
+/// used when the command is a URL.
+pub fn get_backend_kind(cfg: &PlantUMLConfig) -> BackendKind {
     let cmd = cfg.plantuml_cmd.as_deref().unwrap_or({
         if cfg!(target_os = "windows") {
             "java -jar plantuml.jar"
@@ -19,19 +49,35 @@ pub fn create(cfg: &PlantUMLConfig) -> Box<dyn PlantUMLBackend> {
         }
     });
 
-    create_backend(cmd)
+    get_backend_kind_for_cmd(cmd)
+}
+
+/// Get the URL for editing the diagram in the PlantUML server's online editor.
+/// Returns None when no PlantUML server is used.
+#[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+pub fn get_editor_url(cfg: &PlantUMLConfig, plantuml_code: &str) -> Option<String> {
+    let server_url = Url::parse(cfg.plantuml_cmd.as_deref()?).ok()?;
+    PlantUMLServer::new(server_url)
+        .get_editor_url(plantuml_code)
+        .map_err(|e| log::warn!("{}", e))
+        .ok()
+        .map(String::from)
+}
+
+#[cfg(not(any(feature = "plantuml-ssl-server", feature = "plantuml-server")))]
+pub fn get_editor_url(_cfg: &PlantUMLConfig, _plantuml_code: &str) -> Option<String> {
+    None
 }
 
 #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
-fn create_backend(cmd: &str) -> Box<dyn PlantUMLBackend> {
-    if let Ok(server_url) = Url::parse(cmd) {
-        Box::new(PlantUMLServer::new(server_url))
-    } else {
-        Box::new(PlantUMLShell::new(cmd.to_string()))
+fn get_backend_kind_for_cmd(cmd: &str) -> BackendKind {
+    match Url::parse(cmd) {
+        Ok(server_url) => BackendKind::Server(server_url),
+        Err(_) => BackendKind::Shell(cmd.to_string()),
     }
 }
 
 #[cfg(not(any(feature = "plantuml-ssl-server", feature = "plantuml-server")))]
-fn create_backend(cmd: &str) -> Box<dyn PlantUMLBackend> {
-    Box::new(PlantUMLShell::new(cmd.to_string()))
+fn get_backend_kind_for_cmd(cmd: &str) -> BackendKind {
+    BackendKind::Shell(cmd.to_string())
 }
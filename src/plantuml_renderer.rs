@@ -1,16 +1,366 @@
+use crate::build_report::{BuildReport, DiagramRecord};
+use crate::diagram_text;
 use crate::dir_cleaner::DirCleaner;
-use crate::plantuml_backend::PlantUMLBackend;
+use crate::figures::Figure;
+use crate::image_size::get_image_size;
+use crate::plantuml_backend::{
+    get_existing_pages, get_page_filename, PlantUMLBackend, IMAGE_MAP_FORMAT,
+};
 use crate::plantuml_backend_factory;
-use crate::plantumlconfig::PlantUMLConfig;
+use crate::plantumlconfig::{PlantUMLConfig, ZoomMode};
+use crate::progress::Progress;
+use crate::svg_postprocessor::postprocess_svg;
+use anyhow::Result;
 use sha1::{Digest, Sha1};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 pub trait PlantUMLRendererTrait {
-    fn render(&self, plantuml_code: &str, rel_img_url: &str, image_format: String) -> String;
+    fn render(
+        &self,
+        plantuml_code: &str,
+        rel_img_url: &str,
+        image_format: String,
+        attributes: &DiagramAttributes,
+    ) -> String;
+
+    /// Get the (HTML) assets needed by the rendered diagrams, these are added
+    /// once to every chapter containing diagrams.
+    fn get_chapter_assets(&self, _rel_img_url: &str) -> String {
+        String::new()
+    }
+
+    /// Get the (markdown) source view added below a rendered diagram, if any.
+    /// `plantuml_code` is the diagram source as written in the book.
+    fn render_source(&self, _plantuml_code: &str) -> String {
+        String::new()
+    }
+}
+
+/// Per diagram attributes, set using the code block's info string (e.g.
+/// `plantuml,width=300,align=center`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiagramAttributes {
+    /// Value of the img width attribute
+    pub width: Option<String>,
+    /// Value of the img height attribute
+    pub height: Option<String>,
+    /// PlantUML scale directive value (e.g. `1.5`, `2/3` or `max 300*200`)
+    pub scale: Option<String>,
+    /// Horizontal alignment of the image (`left`, `center` or `right`)
+    pub align: Option<String>,
+    /// CSS class(es) to add to the img element
+    pub class: Option<String>,
+    /// Figure id, used as the HTML anchor of the figure
+    pub id: Option<String>,
+    /// Figure caption
+    pub caption: Option<String>,
+    /// The numbered figure, set when the diagram has an id or caption
+    pub figure: Option<Figure>,
+    /// Let the browser load the image lazily (set by the renderer)
+    pub lazy_loading: bool,
+    /// The image's size in pixels (width, height), used when no width or
+    /// height is set (set by the renderer)
+    pub intrinsic_size: Option<(u32, u32)>,
+    /// The (one based) line of the code block in the chapter, diagrams with a
+    /// line are added to the build report (set by the pipeline)
+    pub line: Option<usize>,
+}
+
+impl DiagramAttributes {
+    /// Returns true if the image needs to be emitted as HTML instead of a plain
+    /// markdown image link.
+    fn needs_html(&self) -> bool {
+        self.width.is_some()
+            || self.height.is_some()
+            || self.align.is_some()
+            || self.class.is_some()
+            || self.figure.is_some()
+            || self.lazy_loading
+    }
+}
+
+/// Escape the characters that are not allowed in (double quoted) HTML attribute
+/// values
+pub fn escape_html_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Add a PlantUML directive to every diagram in the code block (i.e. right
+/// after each `@start...` line). When there is no `@start` line the directive
+/// is put at the top.
+/// ditaa diagrams do not support directives, `ditaa_option` is added to the
+/// ditaa options instead (e.g. `@startditaa(scale=2)`), unless that option is
+/// set already.
+fn inject_directive(plantuml_code: &str, directive: &str, ditaa_option: Option<&str>) -> String {
+    let directive = if directive.ends_with('\n') {
+        String::from(directive)
+    } else {
+        format!("{}\n", directive)
+    };
+
+    let mut injected_code = String::with_capacity(plantuml_code.len() + directive.len());
+    let mut injected = false;
+    for line in plantuml_code.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        let start_line = trimmed.trim_start();
+        if start_line.starts_with("@startditaa") {
+            injected = true;
+            match ditaa_option {
+                Some(option) => {
+                    injected_code.push_str(&add_ditaa_option(trimmed, option));
+                    injected_code.push_str(&line[trimmed.len()..]);
+                }
+                None => injected_code.push_str(line),
+            }
+        } else if start_line.starts_with("@start") {
+            injected = true;
+            injected_code.push_str(line);
+            if !line.ends_with('\n') {
+                injected_code.push('\n');
+            }
+            injected_code.push_str(&directive);
+        } else {
+            injected_code.push_str(line);
+        }
+    }
+
+    if injected {
+        injected_code
+    } else {
+        directive + plantuml_code
+    }
+}
+
+/// Add an option to a `@startditaa` line (e.g. `@startditaa(--no-shadows)` ->
+/// `@startditaa(scale=2, --no-shadows)`). Options already present are left
+/// untouched.
+fn add_ditaa_option(start_line: &str, option: &str) -> String {
+    let option_key = option.split('=').next().unwrap_or(option);
+    if start_line.contains(&format!("{}=", option_key)) {
+        String::from(start_line)
+    } else if let Some(options_start) = start_line.find('(') {
+        format!(
+            "{}{}, {}",
+            &start_line[..=options_start],
+            option,
+            &start_line[options_start + 1..]
+        )
+    } else {
+        format!("{}({})", start_line, option)
+    }
+}
+
+/// Get the ditaa `scale=` option for the scale attribute of a diagram. ditaa
+/// only supports a plain number, other PlantUML scale forms (e.g. `2/3` or
+/// `max 300*200`) are ignored for ditaa diagrams (with a warning).
+fn get_ditaa_scale_option(plantuml_code: &str, scale: &str) -> Option<String> {
+    let scale = scale.trim();
+    match scale.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Some(format!("scale={}", scale)),
+        _ => {
+            if plantuml_code.contains("@startditaa") {
+                log::warn!(
+                    "Ignoring scale '{}' for a ditaa diagram, ditaa only supports a plain \
+                     number.",
+                    scale
+                );
+            }
+            None
+        }
+    }
+}
+
+/// Add the PlantUML directives for rendering the diagram at twice the default
+/// resolution. Regular diagrams get a `skinparam dpi` directive, ditaa diagrams
+/// (which ignore skinparams) get a `scale=2` option, or their scale option is
+/// doubled. Returns None if a ditaa scale option is not a number.
+fn inject_hidpi_directive(plantuml_code: &str) -> Option<String> {
+    let mut scaled_code = String::with_capacity(plantuml_code.len());
+    for line in plantuml_code.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed.trim_start().starts_with("@startditaa") && trimmed.contains("scale=") {
+            scaled_code.push_str(&double_ditaa_scale(trimmed)?);
+            scaled_code.push_str(&line[trimmed.len()..]);
+        } else {
+            scaled_code.push_str(line);
+        }
+    }
+
+    Some(inject_directive(
+        &scaled_code,
+        "skinparam dpi 192",
+        Some("scale=2"),
+    ))
+}
+
+/// Double the scale option of a `@startditaa` line (e.g.
+/// `@startditaa(scale=1.5)` -> `@startditaa(scale=3)`). Returns None if the
+/// scale is not a number.
+fn double_ditaa_scale(start_line: &str) -> Option<String> {
+    let value_start = start_line.find("scale=")? + "scale=".len();
+    let value_end = start_line[value_start..]
+        .find(|c| c == ',' || c == ')')
+        .map_or(start_line.len(), |end| value_start + end);
+    let scale: f64 = start_line[value_start..value_end].trim().parse().ok()?;
+
+    Some(format!(
+        "{}{}{}",
+        &start_line[..value_start],
+        scale * 2.0,
+        &start_line[value_end..]
+    ))
+}
+
+/// Returns true for png images (braille png images excluded)
+fn is_png_image(image_path: &Path) -> bool {
+    let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+    file_name.ends_with(".png") && !file_name.ends_with(".braille.png")
+}
+
+/// Returns true for text diagrams, which are inlined in the markdown
+fn is_inline_text(image_path: &Path) -> bool {
+    let extension = image_path.extension().unwrap_or_default();
+    extension == "atxt" || extension == "utxt"
+}
+
+/// Give a client side image map (as generated by PlantUML) a unique name,
+/// PlantUML always uses the same name, which clashes when a page contains
+/// multiple diagrams. Empty lines are dropped to keep the map in one HTML
+/// block. Relative links are resolved against the image dir (like they are for
+/// svg images), while the map itself is part of the chapter.
+/// Returns None if the map has no areas (i.e. the diagram has no links).
+fn rename_image_map(cmapx: &str, name: &str, rel_img_url: &str) -> Option<String> {
+    let map_start = cmapx.find("<map")?;
+    let map_tag_end = map_start + cmapx[map_start..].find('>')?;
+    if !cmapx.contains("<area") {
+        return None;
+    }
+
+    let mut map = format!("<map id=\"{0}\" name=\"{0}\">", name);
+    for line in cmapx[map_tag_end + 1..].lines() {
+        if !line.trim().is_empty() {
+            map.push('\n');
+            map.push_str(&rebase_relative_hrefs(line.trim_end(), rel_img_url));
+        }
+    }
+
+    Some(map)
+}
+
+/// Prefix the relative href attribute values in `html` with `base_url`
+fn rebase_relative_hrefs(html: &str, base_url: &str) -> String {
+    const HREF: &str = "href=\"";
+
+    let mut rebased = String::with_capacity(html.len());
+    let mut pos = 0;
+    while let Some(value_start) = html[pos..].find(HREF).map(|p| p + pos + HREF.len()) {
+        rebased.push_str(&html[pos..value_start]);
+        let href = &html[value_start..];
+        let value = &href[..href.find('"').unwrap_or(href.len())];
+        let is_absolute = value.starts_with('/') || value.starts_with('#') || value.contains(':');
+        if !is_absolute {
+            rebased.push_str(base_url);
+            rebased.push('/');
+        }
+        pos = value_start;
+    }
+    rebased.push_str(&html[pos..]);
+
+    rebased
+}
+
+/// Inline style hiding an element visually, while keeping it available to
+/// screen readers and mdbook's search index
+const VISUALLY_HIDDEN_STYLE: &str = "position: absolute; width: 1px; height: 1px; \
+     overflow: hidden; clip: rect(0 0 0 0); white-space: nowrap;";
+
+/// Get the file name of the double resolution variant of an image (foo.png ->
+/// foo@2x.png)
+pub fn get_hidpi_filename(image_path: &Path) -> PathBuf {
+    let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+    let hidpi_name = match file_name.find('.') {
+        Some(dot) => format!("{}@2x{}", &file_name[..dot], &file_name[dot..]),
+        None => format!("{}@2x", file_name),
+    };
+
+    image_path.with_file_name(hidpi_name)
+}
+
+/// An image file and its (optional) double resolution variant and client side
+/// image map
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ImageFiles {
+    file: PathBuf,
+    hidpi_file: Option<PathBuf>,
+    map_file: Option<PathBuf>,
+}
+
+impl ImageFiles {
+    /// Get the image files of the given page of a (multi page) diagram, or None
+    /// if the page does not exist.
+    fn page(&self, page: usize) -> Option<Self> {
+        let file = get_page_filename(&self.file, page);
+        if !file.exists() {
+            return None;
+        }
+
+        let get_page = |variant: &Option<PathBuf>| {
+            variant
+                .as_ref()
+                .map(|f| get_page_filename(f, page))
+                .filter(|f| f.exists())
+        };
+
+        Some(Self {
+            hidpi_file: get_page(&self.hidpi_file),
+            map_file: get_page(&self.map_file),
+            file,
+        })
+    }
+}
+
+/// The image files of a single rendered diagram page
+struct PageImages {
+    /// The (regular) image
+    image: ImageFiles,
+    /// The dark theme variant of the image (if any)
+    dark_image: Option<ImageFiles>,
 }
 
+/// The CSS showing the dark theme variants of the diagrams when a dark mdbook
+/// theme is active, and the regular ones otherwise.
+const DARK_THEME_CSS: &str = "<style>\n\
+.mdbook-plantuml-dark { display: none; }\n\
+html.coal .mdbook-plantuml-dark, html.navy .mdbook-plantuml-dark, html.ayu .mdbook-plantuml-dark { display: inline; }\n\
+html.coal .mdbook-plantuml-light, html.navy .mdbook-plantuml-light, html.ayu .mdbook-plantuml-light { display: none; }\n\
+</style>\n";
+
+/// The lightbox script and stylesheet, written to the image dir when the
+/// lightbox zoom mode is enabled
+const LIGHTBOX_JS: (&str, &str) = (
+    "mdbook-plantuml-lightbox.js",
+    include_str!("assets/lightbox.js"),
+);
+const LIGHTBOX_CSS: (&str, &str) = (
+    "mdbook-plantuml-lightbox.css",
+    include_str!("assets/lightbox.css"),
+);
+
 /// Create the image names with the appropriate extension and path
 /// The base name of the file is a SHA1 of the code block to avoid collisions
 /// with existing and as a bonus prevent duplicate files.
@@ -28,6 +378,8 @@ pub fn get_image_filename(img_root: &Path, plantuml_code: &str, image_format: &s
         } else if image_format == "braille" {
             // -tbraille outputs a .braille.png file
             "braille.png"
+        } else if image_format == IMAGE_MAP_FORMAT {
+            "cmapx"
         } else {
             image_format
         }
@@ -46,20 +398,112 @@ fn hash_string(code: &str) -> String {
 pub struct PlantUMLRenderer {
     backend: Box<dyn PlantUMLBackend>,
     cleaner: RefCell<DirCleaner>,
+    report: RefCell<BuildReport>,
+    progress: RefCell<Option<Progress>>,
+    log_failures: Cell<bool>,
     img_root: PathBuf,
-    clickable_img: bool,
+    cfg: PlantUMLConfig,
 }
 
 impl PlantUMLRenderer {
     pub fn new(cfg: &PlantUMLConfig, img_root: &Path) -> Self {
-        let renderer = Self {
-            backend: plantuml_backend_factory::create(cfg),
+        Self::with_backend(cfg, img_root, plantuml_backend_factory::create(cfg))
+    }
+
+    /// Create a renderer using the given backend instead of the configured one
+    pub fn with_backend(
+        cfg: &PlantUMLConfig,
+        img_root: &Path,
+        backend: Box<dyn PlantUMLBackend>,
+    ) -> Self {
+        Self {
+            backend,
             cleaner: RefCell::new(DirCleaner::new(img_root)),
+            report: RefCell::new(BuildReport::new(
+                plantuml_backend_factory::get_backend_kind(cfg).name(),
+            )),
+            progress: RefCell::default(),
+            log_failures: Cell::new(true),
             img_root: img_root.to_path_buf(),
-            clickable_img: cfg.clickable_img,
-        };
+            cfg: cfg.clone(),
+        }
+    }
+
+    /// Keep all the existing files in the image dir, instead of removing the
+    /// ones not used by the rendered diagrams. Used when the image dir is not
+    /// the book's (e.g. when rendering a single file).
+    pub fn disable_cleanup(&self) {
+        self.cleaner.borrow_mut().keep_all();
+    }
+
+    /// Don't log failures to render diagrams (the errors are still rendered
+    /// into the chapters). Used when failures are expected, e.g. when only
+    /// the cached images are used.
+    pub fn disable_failure_logging(&self) {
+        self.log_failures.set(false);
+    }
+
+    /// Get the files in the image dir that are not used by the diagrams
+    /// rendered so far. These are removed when the renderer is dropped (unless
+    /// cleanup is disabled).
+    pub fn get_obsolete_files(&self) -> Vec<PathBuf> {
+        self.cleaner.borrow().get_obsolete_files()
+    }
+
+    /// Start rendering the diagrams of a chapter, the diagrams rendered after
+    /// this are reported as part of it
+    pub fn start_chapter(&self, chapter_path: &Path) {
+        self.report.borrow_mut().start_chapter(chapter_path);
+    }
+
+    /// Show the progress of rendering the book's `total` diagrams on stderr
+    /// (when it is a terminal)
+    pub fn show_progress(&self, total: usize) {
+        *self.progress.borrow_mut() = Progress::for_stderr(total);
+    }
+
+    /// Write the build report of the diagrams rendered so far to `report_file`
+    pub fn write_report(&self, report_file: &Path) -> Result<()> {
+        self.report.borrow().write(report_file)
+    }
+
+    fn log_failure(&self, level: log::Level, message: fmt::Arguments) {
+        if self.log_failures.get() {
+            log::log!(level, "{}", message);
+        }
+    }
+
+    /// Write the lightbox script and stylesheet to the image dir, used by the
+    /// chapters when the lightbox zoom mode is enabled. Returns the asset files.
+    pub fn write_lightbox_assets(&self) -> Vec<PathBuf> {
+        for (name, content) in [LIGHTBOX_JS, LIGHTBOX_CSS] {
+            let asset_file = self.img_root.join(name);
+            // Only write changed assets, rewriting the file would trigger
+            // another rebuild when using mdbook serve
+            let is_current = fs::read_to_string(&asset_file).map_or(false, |c| c == content);
+            if !is_current {
+                if let Err(e) = fs::write(&asset_file, content) {
+                    log::error!("Failed to write {:?} ({}).", asset_file, e);
+                }
+            }
+        }
 
-        renderer
+        self.keep_lightbox_assets()
+    }
+
+    /// Flag the lightbox assets as used without writing them, so the cleaner
+    /// does not remove them. Returns the asset files.
+    pub fn keep_lightbox_assets(&self) -> Vec<PathBuf> {
+        let asset_files: Vec<PathBuf> = [LIGHTBOX_JS, LIGHTBOX_CSS]
+            .iter()
+            .map(|(name, _)| self.img_root.join(name))
+            .collect();
+        let mut cleaner = self.cleaner.borrow_mut();
+        for asset_file in &asset_files {
+            cleaner.keep(asset_file);
+        }
+
+        asset_files
     }
 
     fn create_md_link(rel_img_url: &str, image_path: &Path, clickable: bool) -> String {
@@ -75,6 +519,146 @@ impl PlantUMLRenderer {
         }
     }
 
+    fn get_img_url(rel_img_url: &str, image_path: &Path) -> String {
+        escape_html_attribute(&format!(
+            "{}/{}",
+            rel_img_url,
+            image_path.file_name().unwrap().to_str().unwrap()
+        ))
+    }
+
+    fn create_img_element(
+        rel_img_url: &str,
+        image: &ImageFiles,
+        clickable: bool,
+        attributes: &DiagramAttributes,
+        variant_class: Option<&str>,
+    ) -> String {
+        let img_url = Self::get_img_url(rel_img_url, &image.file);
+        let image_map = image.map_file.as_ref().and_then(|map_file| {
+            let map_name = format!(
+                "mdbook-plantuml-{}",
+                map_file.file_stem().unwrap_or_default().to_string_lossy()
+            );
+            let cmapx = fs::read_to_string(map_file)
+                .map_err(|e| log::warn!("Failed to read image map {:?} ({}).", map_file, e))
+                .ok()?;
+            rename_image_map(&cmapx, &map_name, rel_img_url).map(|map| (map_name, map))
+        });
+
+        let mut img = format!("<img src=\"{}\"", img_url);
+        if let Some(hidpi_file) = &image.hidpi_file {
+            img.push_str(&format!(
+                " srcset=\"{} 1x, {} 2x\"",
+                img_url,
+                Self::get_img_url(rel_img_url, hidpi_file)
+            ));
+        }
+        img.push_str(" alt=\"\"");
+
+        let class = match (variant_class, &attributes.class) {
+            (Some(variant_class), Some(class)) => Some(format!("{} {}", variant_class, class)),
+            (Some(variant_class), None) => Some(String::from(variant_class)),
+            (None, class) => class.clone(),
+        };
+        let optional_attributes = [
+            ("width", &attributes.width),
+            ("height", &attributes.height),
+            ("class", &class),
+        ];
+        for (name, value) in optional_attributes {
+            if let Some(value) = value {
+                img.push_str(&format!(" {}=\"{}\"", name, escape_html_attribute(value)));
+            }
+        }
+        if attributes.lazy_loading {
+            img.push_str(" loading=\"lazy\" decoding=\"async\"");
+        }
+        if let (Some((width, height)), None, None) = (
+            attributes.intrinsic_size,
+            &attributes.width,
+            &attributes.height,
+        ) {
+            // The height must scale along when the image is narrowed down to
+            // the content width
+            img.push_str(&format!(
+                " width=\"{}\" height=\"{}\" style=\"height: auto;\"",
+                width, height
+            ));
+        }
+        if let Some((map_name, _)) = &image_map {
+            img.push_str(&format!(" usemap=\"#{}\"", map_name));
+        }
+        img.push('>');
+
+        if clickable {
+            img = format!("<a href=\"{}\">{}</a>", img_url, img);
+        }
+
+        if let Some((_, map)) = image_map {
+            img.push('\n');
+            img.push_str(&map);
+        }
+
+        img
+    }
+
+    fn create_html_image(
+        rel_img_url: &str,
+        page: &PageImages,
+        clickable: bool,
+        attributes: &DiagramAttributes,
+    ) -> String {
+        let img = match &page.dark_image {
+            Some(dark_image) => {
+                let light = Self::create_img_element(
+                    rel_img_url,
+                    &page.image,
+                    clickable,
+                    attributes,
+                    Some("mdbook-plantuml-light"),
+                );
+                let dark = Self::create_img_element(
+                    rel_img_url,
+                    dark_image,
+                    clickable,
+                    attributes,
+                    Some("mdbook-plantuml-dark"),
+                );
+                light + &dark
+            }
+            None => Self::create_img_element(rel_img_url, &page.image, clickable, attributes, None),
+        };
+
+        // Figures are aligned as a whole (see create_figure)
+        if let (Some(align), None) = (&attributes.align, &attributes.figure) {
+            format!(
+                "<figure style=\"text-align: {};\">{}</figure>\n\n",
+                escape_html_attribute(align),
+                img
+            )
+        } else {
+            format!("{}\n\n", img)
+        }
+    }
+
+    /// Wrap the rendered diagram (all of its pages) in a figure with an anchor
+    /// and the figure label and caption as caption
+    fn create_figure(rendered: &str, figure: &Figure, attributes: &DiagramAttributes) -> String {
+        let style = attributes.align.as_ref().map_or(String::new(), |align| {
+            format!(" style=\"text-align: {};\"", escape_html_attribute(align))
+        });
+
+        format!(
+            "<figure id=\"{}\" class=\"mdbook-plantuml-figure\"{}>\n\n{}\n\n\
+             <figcaption>{}</figcaption>\n</figure>\n\n",
+            escape_html_attribute(&figure.id),
+            style,
+            rendered.trim_end(),
+            escape_html_attribute(&figure.get_title())
+        )
+    }
+
     fn create_inline_image(image_path: &Path) -> String {
         log::debug!("Creating inline image from {:?}", image_path);
         let raw_source = fs::read(image_path).unwrap();
@@ -82,56 +666,677 @@ impl PlantUMLRenderer {
         format!("\n```txt\n{}```\n", txt)
     }
 
-    pub fn render(&self, plantuml_code: &str, rel_img_url: &str, image_format: &str) -> String {
-        let output_file = get_image_filename(&self.img_root, plantuml_code, image_format);
-        if !output_file.exists() {
-            if let Err(e) =
-                self.backend
-                    .render_from_string(plantuml_code, image_format, &output_file)
-            {
-                log::error!("Failed to generate PlantUML diagram.");
-                return format!("\nPlantUML rendering error:\n{}\n\n", e);
-            }
-        }
+    pub fn render(
+        &self,
+        plantuml_code: &str,
+        rel_img_url: &str,
+        image_format: &str,
+        attributes: &DiagramAttributes,
+    ) -> String {
+        let scaled_code;
+        let plantuml_code = if let Some(scale) = &attributes.scale {
+            scaled_code = inject_directive(
+                plantuml_code,
+                &format!("scale {}", scale),
+                get_ditaa_scale_option(plantuml_code, scale).as_deref(),
+            );
+            scaled_code.as_str()
+        } else {
+            plantuml_code
+        };
+
+        let output_file = get_image_filename(&self.img_root, plantuml_code, image_format);
+        let mut record = DiagramRecord::new(
+            attributes.line.unwrap_or_default(),
+            &output_file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            image_format,
+        );
+        record.cache_hit = output_file.exists();
+        let started = Instant::now();
+        let image = match self.render_image_files(plantuml_code, image_format, output_file) {
+            Ok(image) => image,
+            Err(e) => {
+                self.log_failure(
+                    log::Level::Error,
+                    format_args!("Failed to generate PlantUML diagram."),
+                );
+                record.set_duration(started.elapsed());
+                record.error = Some(e.to_string());
+                self.finish_diagram(attributes, record);
+                return format!("\nPlantUML rendering error:\n{}\n\n", e);
+            }
+        };
+
+        let dark_image = if is_inline_text(&image.file) {
+            None
+        } else {
+            self.render_dark_variant(plantuml_code, image_format)
+        };
+        record.set_duration(started.elapsed());
+
+        // Multi page diagrams result in multiple image files, one per page
+        let mut rendered = String::new();
+        for page_index in 0.. {
+            let page = match image.page(page_index) {
+                Some(page_image) => PageImages {
+                    image: page_image,
+                    dark_image: dark_image.as_ref().and_then(|f| f.page(page_index)),
+                },
+                None => break,
+            };
+            self.keep(&page.image);
+            if let Some(dark_image) = &page.dark_image {
+                self.keep(dark_image);
+            }
+
+            let page_file = &page.image.file;
+            record.output_size += fs::metadata(page_file).map_or(0, |m| m.len());
+            let lazy_attributes;
+            let attributes = if self.cfg.lazy_loading {
+                lazy_attributes = DiagramAttributes {
+                    lazy_loading: true,
+                    intrinsic_size: get_image_size(page_file),
+                    ..attributes.clone()
+                };
+                &lazy_attributes
+            } else {
+                attributes
+            };
+
+            if is_inline_text(page_file) {
+                rendered.push_str(&Self::create_inline_image(page_file));
+            } else if attributes.needs_html()
+                || page.image.hidpi_file.is_some()
+                || page.image.map_file.is_some()
+                || page.dark_image.is_some()
+            {
+                rendered.push_str(&Self::create_html_image(
+                    rel_img_url,
+                    &page,
+                    self.cfg.clickable_img,
+                    attributes,
+                ));
+            } else {
+                rendered.push_str(&Self::create_md_link(
+                    rel_img_url,
+                    page_file,
+                    self.cfg.clickable_img,
+                ));
+            }
+
+            if self.cfg.searchable_text && !is_inline_text(page_file) {
+                rendered.push_str(&Self::create_text_element(
+                    plantuml_code,
+                    page_file,
+                    page_index,
+                ));
+            }
+        }
+        self.finish_diagram(attributes, record);
+
+        match &attributes.figure {
+            Some(figure) => Self::create_figure(&rendered, figure, attributes),
+            None => rendered,
+        }
+    }
+
+    /// Add the diagram to the build report and progress if it is one of the
+    /// book's code blocks (i.e. not a figure list thumbnail)
+    fn finish_diagram(&self, attributes: &DiagramAttributes, record: DiagramRecord) {
+        if attributes.line.is_some() {
+            if let Some(progress) = self.progress.borrow_mut().as_mut() {
+                progress.advance(record.cache_hit);
+            }
+            self.report.borrow_mut().add_diagram(record);
+        }
+    }
+
+    /// Create a visually hidden element containing the diagram's text labels,
+    /// so the diagram text ends up in the search index (and is available to
+    /// screen readers). The labels are extracted from svg images, for other
+    /// formats they are extracted from the source (for the first page only).
+    fn create_text_element(plantuml_code: &str, page_file: &Path, page_index: usize) -> String {
+        let labels = if page_file.extension().unwrap_or_default() == "svg" {
+            fs::read_to_string(page_file)
+                .map(|svg| diagram_text::extract_svg_text(&svg))
+                .unwrap_or_else(|e| {
+                    log::warn!("Failed to read the text of {:?} ({}).", page_file, e);
+                    Vec::new()
+                })
+        } else if page_index == 0 {
+            diagram_text::extract_source_text(plantuml_code)
+        } else {
+            Vec::new()
+        };
+
+        if labels.is_empty() {
+            return String::new();
+        }
+
+        format!(
+            "<div class=\"mdbook-plantuml-text\" style=\"{}\">{}</div>\n\n",
+            VISUALLY_HIDDEN_STYLE,
+            labels.join(" ")
+        )
+    }
+
+    /// Returns the assets to add to every chapter containing diagrams
+    pub fn get_chapter_assets(&self, rel_img_url: &str) -> String {
+        let mut assets = String::new();
+        if self.get_dark_preamble().is_some() {
+            assets.push_str(DARK_THEME_CSS);
+        }
+        if self.cfg.zoom == Some(ZoomMode::Lightbox) {
+            assets.push_str(&format!(
+                "<link rel=\"stylesheet\" href=\"{0}/{1}\">\n\
+                 <script src=\"{0}/{2}\"></script>\n",
+                rel_img_url, LIGHTBOX_CSS.0, LIGHTBOX_JS.0
+            ));
+        }
+
+        assets
+    }
+
+    /// Returns a collapsible block with the diagram source (as a fenced code
+    /// block, so mdbook adds its copy button) when show-source is enabled.
+    pub fn render_source(&self, plantuml_code: &str) -> String {
+        if !self.cfg.show_source {
+            return String::new();
+        }
+
+        // The fence must be longer than any backtick run in the code
+        let mut longest_run = 0;
+        let mut run = 0;
+        for c in plantuml_code.chars() {
+            run = if c == '`' { run + 1 } else { 0 };
+            longest_run = longest_run.max(run);
+        }
+        let fence = "`".repeat((longest_run + 1).max(3));
+
+        let mut source = String::from(
+            "\n<details class=\"mdbook-plantuml-source\">\n<summary>PlantUML source</summary>\n\n",
+        );
+        source.push_str(&format!("{}plantuml\n{}", fence, plantuml_code));
+        if !plantuml_code.ends_with('\n') {
+            source.push('\n');
+        }
+        source.push_str(&format!("{}\n\n", fence));
+        if let Some(editor_url) = plantuml_backend_factory::get_editor_url(&self.cfg, plantuml_code)
+        {
+            source.push_str(&format!(
+                "<p><a href=\"{}\" target=\"_blank\">Open in PlantUML editor</a></p>\n\n",
+                escape_html_attribute(&editor_url)
+            ));
+        }
+        source.push_str("</details>\n\n");
+
+        source
+    }
+
+    /// Render a diagram to image files only (i.e. without creating the markdown
+    /// for it). Returns the image files of all pages, including the double
+    /// resolution, image map and dark theme variants.
+    pub fn render_images(&self, plantuml_code: &str, image_format: &str) -> Result<Vec<PathBuf>> {
+        let output_file = get_image_filename(&self.img_root, plantuml_code, image_format);
+        let image = self.render_image_files(plantuml_code, image_format, output_file)?;
+        let dark_image = if is_inline_text(&image.file) {
+            None
+        } else {
+            self.render_dark_variant(plantuml_code, image_format)
+        };
+
+        let mut files = Vec::new();
+        for image in std::iter::once(image).chain(dark_image) {
+            for page in (0..).map_while(|page_index| image.page(page_index)) {
+                self.keep(&page);
+                files.push(page.file);
+                files.extend(page.hidpi_file);
+                files.extend(page.map_file);
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Flag the image files as used, so the cleaner does not remove them
+    fn keep(&self, image: &ImageFiles) {
+        let mut cleaner = self.cleaner.borrow_mut();
+        cleaner.keep(&image.file);
+        for variant in [&image.hidpi_file, &image.map_file]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            cleaner.keep(variant);
+        }
+    }
+
+    /// Render the diagram to `output_file` (if it is not cached already),
+    /// together with its double resolution variant when enabled, and the client
+    /// side image map for png diagrams with links.
+    fn render_image_files(
+        &self,
+        plantuml_code: &str,
+        image_format: &str,
+        output_file: PathBuf,
+    ) -> Result<ImageFiles> {
+        if !output_file.exists() {
+            self.backend
+                .render_from_string(plantuml_code, image_format, &output_file)?;
+            if output_file.extension().unwrap_or_default() == "svg" {
+                self.postprocess_svg_pages(&output_file);
+            }
+        }
+
+        let hidpi_file = if self.cfg.hidpi_png && is_png_image(&output_file) {
+            self.render_hidpi_variant(plantuml_code, image_format, &output_file)
+        } else {
+            None
+        };
+
+        let map_file = if is_png_image(&output_file) && plantuml_code.contains("[[") {
+            self.render_image_map(plantuml_code)
+        } else {
+            None
+        };
+
+        Ok(ImageFiles {
+            file: output_file,
+            hidpi_file,
+            map_file,
+        })
+    }
+
+    /// Post-process the freshly rendered svg pages of a diagram in place, so the
+    /// cached images are the post-processed ones
+    fn postprocess_svg_pages(&self, first_page: &Path) {
+        for page in get_existing_pages(first_page) {
+            let result =
+                fs::read_to_string(&page).and_then(|svg| match postprocess_svg(&svg, &self.cfg) {
+                    Some(processed) => fs::write(&page, processed),
+                    None => Ok(()),
+                });
+            if let Err(e) = result {
+                log::warn!("Failed to post-process {:?} ({}).", page, e);
+            }
+        }
+    }
+
+    /// Render the client side image map of a diagram (if it is not cached
+    /// already), so links in png diagrams work. Returns the file name of the
+    /// first page, or None if rendering failed (no map is used in that case).
+    fn render_image_map(&self, plantuml_code: &str) -> Option<PathBuf> {
+        let map_file = get_image_filename(&self.img_root, plantuml_code, IMAGE_MAP_FORMAT);
+        if !map_file.exists() {
+            if let Err(e) =
+                self.backend
+                    .render_from_string(plantuml_code, IMAGE_MAP_FORMAT, &map_file)
+            {
+                self.log_failure(
+                    log::Level::Warn,
+                    format_args!(
+                        "Failed to generate the image map of a PlantUML diagram ({}).",
+                        e
+                    ),
+                );
+                return None;
+            }
+        }
+
+        Some(map_file)
+    }
+
+    /// Render the double resolution variant of a png diagram (if it is not
+    /// cached already). Returns the file name of the first page, or None if
+    /// rendering failed (the regular image is used in that case).
+    fn render_hidpi_variant(
+        &self,
+        plantuml_code: &str,
+        image_format: &str,
+        output_file: &Path,
+    ) -> Option<PathBuf> {
+        let hidpi_file = get_hidpi_filename(output_file);
+        if !hidpi_file.exists() {
+            let hidpi_code = match inject_hidpi_directive(plantuml_code) {
+                Some(hidpi_code) => hidpi_code,
+                None => {
+                    log::warn!(
+                        "Cannot double the ditaa scale of a PlantUML diagram, it has no HiDPI \
+                         variant."
+                    );
+                    return None;
+                }
+            };
+            if let Err(e) = self
+                .backend
+                .render_from_string(&hidpi_code, image_format, &hidpi_file)
+            {
+                self.log_failure(
+                    log::Level::Warn,
+                    format_args!(
+                        "Failed to generate the HiDPI variant of a PlantUML diagram ({}).",
+                        e
+                    ),
+                );
+                return None;
+            }
+        }
+
+        Some(hidpi_file)
+    }
+
+    /// The PlantUML preamble for the dark theme variant of the diagrams, or
+    /// None if dark theme variants are disabled.
+    fn get_dark_preamble(&self) -> Option<String> {
+        match (&self.cfg.dark_theme, &self.cfg.dark_preamble) {
+            (Some(theme), Some(preamble)) => Some(format!("!theme {}\n{}", theme, preamble)),
+            (Some(theme), None) => Some(format!("!theme {}", theme)),
+            (None, Some(preamble)) => Some(preamble.clone()),
+            (None, None) => None,
+        }
+    }
+
+    /// Render the dark theme variant of a diagram (if enabled). The dark
+    /// variant is named after its own source (i.e. including the dark
+    /// preamble), so changing the preamble invalidates the cached images.
+    /// Returns None if dark variants are disabled, not supported for the
+    /// diagram type (ditaa), or rendering failed.
+    fn render_dark_variant(&self, plantuml_code: &str, image_format: &str) -> Option<ImageFiles> {
+        let preamble = self.get_dark_preamble()?;
+        let dark_code = inject_directive(plantuml_code, &preamble, None);
+        if dark_code == plantuml_code {
+            return None;
+        }
+
+        let dark_file = get_image_filename(&self.img_root, &dark_code, image_format);
+        self.render_image_files(&dark_code, image_format, dark_file)
+            .map_err(|e| {
+                self.log_failure(
+                    log::Level::Warn,
+                    format_args!(
+                        "Failed to generate the dark theme variant of a PlantUML diagram ({}).",
+                        e
+                    ),
+                );
+            })
+            .ok()
+    }
+}
+
+impl PlantUMLRendererTrait for PlantUMLRenderer {
+    fn render(
+        &self,
+        plantuml_code: &str,
+        rel_img_url: &str,
+        image_format: String,
+        attributes: &DiagramAttributes,
+    ) -> String {
+        Self::render(self, plantuml_code, rel_img_url, &image_format, attributes)
+    }
+
+    fn get_chapter_assets(&self, rel_img_url: &str) -> String {
+        Self::get_chapter_assets(self, rel_img_url)
+    }
+
+    fn render_source(&self, plantuml_code: &str) -> String {
+        Self::render_source(self, plantuml_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::figures::{FigureIndex, FigureNumbering};
+    use crate::plantuml_backend::{count_pages, SyntaxError};
+    use anyhow::{bail, Result};
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    fn create_test_renderer(cfg: PlantUMLConfig, img_root: &Path, is_ok: bool) -> PlantUMLRenderer {
+        PlantUMLRenderer::with_backend(&cfg, img_root, Box::new(BackendMock { is_ok }))
+    }
+
+    #[test]
+    fn test_create_md_link() {
+        assert_eq!(
+            String::from("![](foo/bar/baz.svg)\n\n"),
+            PlantUMLRenderer::create_md_link("foo/bar", Path::new("/froboz/baz.svg"), false)
+        );
+
+        assert_eq!(
+            "![](/baz.svg)\n\n",
+            PlantUMLRenderer::create_md_link("", Path::new("baz.svg"), false)
+        );
+
+        assert_eq!(
+            String::from("![](/baz.svg)\n\n"),
+            PlantUMLRenderer::create_md_link("", Path::new("foo/baz.svg"), false)
+        );
+    }
+
+    #[test]
+    fn test_create_html_image() {
+        let page = PageImages {
+            image: ImageFiles {
+                file: PathBuf::from("/froboz/baz.svg"),
+                hidpi_file: None,
+                map_file: None,
+            },
+            dark_image: None,
+        };
+        let attributes = DiagramAttributes {
+            width: Some(String::from("300")),
+            height: Some(String::from("50%")),
+            class: Some(String::from("foo \"bar\"")),
+            ..DiagramAttributes::default()
+        };
+        assert_eq!(
+            "<img src=\"foo/baz.svg\" alt=\"\" width=\"300\" height=\"50%\" class=\"foo &quot;bar&quot;\">\n\n",
+            PlantUMLRenderer::create_html_image("foo", &page, false, &attributes)
+        );
+
+        let attributes = DiagramAttributes {
+            align: Some(String::from("center")),
+            ..DiagramAttributes::default()
+        };
+        assert_eq!(
+            "<figure style=\"text-align: center;\"><a href=\"foo/baz.svg\"><img src=\"foo/baz.svg\" alt=\"\"></a></figure>\n\n",
+            PlantUMLRenderer::create_html_image("foo", &page, true, &attributes)
+        );
+    }
+
+    #[test]
+    fn test_create_html_image_hidpi() {
+        let page = PageImages {
+            image: ImageFiles {
+                file: PathBuf::from("/froboz/baz.png"),
+                hidpi_file: Some(PathBuf::from("/froboz/baz@2x.png")),
+                map_file: None,
+            },
+            dark_image: None,
+        };
+        assert_eq!(
+            "<img src=\"foo/baz.png\" srcset=\"foo/baz.png 1x, foo/baz@2x.png 2x\" alt=\"\">\n\n",
+            PlantUMLRenderer::create_html_image("foo", &page, false, &DiagramAttributes::default())
+        );
+    }
+
+    #[test]
+    fn test_inject_hidpi_directive() {
+        assert_eq!(
+            Some("@startuml\nskinparam dpi 192\nA --|> B\n@enduml\n"),
+            inject_hidpi_directive("@startuml\nA --|> B\n@enduml\n").as_deref()
+        );
+        assert_eq!(
+            Some("@startuml\nskinparam dpi 192\n"),
+            inject_hidpi_directive("@startuml").as_deref()
+        );
+        assert_eq!(
+            Some("@startditaa(scale=2)\r\n+--+\n@endditaa"),
+            inject_hidpi_directive("@startditaa\r\n+--+\n@endditaa").as_deref()
+        );
+        assert_eq!(
+            Some("@startditaa(scale=2, --no-shadows)\n+--+\n@endditaa"),
+            inject_hidpi_directive("@startditaa(--no-shadows)\n+--+\n@endditaa").as_deref()
+        );
+        // An existing scale is doubled
+        assert_eq!(
+            Some("@startditaa(scale=6)\n"),
+            inject_hidpi_directive("@startditaa(scale=3)\n").as_deref()
+        );
+        assert_eq!(
+            Some("@startditaa(--no-shadows, scale=1.5)\n"),
+            inject_hidpi_directive("@startditaa(--no-shadows, scale=0.75)\n").as_deref()
+        );
+        // No double resolution variant when the scale is not a number
+        assert_eq!(None, inject_hidpi_directive("@startditaa(scale=big)\n"));
+    }
+
+    #[test]
+    fn test_get_hidpi_filename() {
+        assert_eq!(
+            PathBuf::from("foo/bar@2x.png"),
+            get_hidpi_filename(Path::new("foo/bar.png"))
+        );
+        assert!(is_png_image(Path::new("foo/bar.png")));
+        assert!(!is_png_image(Path::new("foo/bar.svg")));
+        assert!(!is_png_image(Path::new("foo/bar.braille.png")));
+    }
+
+    #[test]
+    fn test_create_html_image_dark_variant() {
+        let page = PageImages {
+            image: ImageFiles {
+                file: PathBuf::from("/froboz/light.svg"),
+                hidpi_file: None,
+                map_file: None,
+            },
+            dark_image: Some(ImageFiles {
+                file: PathBuf::from("/froboz/dark.svg"),
+                hidpi_file: None,
+                map_file: None,
+            }),
+        };
+        let attributes = DiagramAttributes {
+            class: Some(String::from("wide")),
+            ..DiagramAttributes::default()
+        };
+        assert_eq!(
+            "<img src=\"foo/light.svg\" alt=\"\" class=\"mdbook-plantuml-light wide\">\
+             <img src=\"foo/dark.svg\" alt=\"\" class=\"mdbook-plantuml-dark wide\">\n\n",
+            PlantUMLRenderer::create_html_image("foo", &page, false, &attributes)
+        );
+    }
+
+    #[test]
+    fn test_rename_image_map() {
+        let cmapx = "<map id=\"plantuml_map\" name=\"plantuml_map\">\n\
+                     <area shape=\"rect\" id=\"id1\" href=\"foo.html\" coords=\"1,2,3,4\"/>\n\
+                     \n\
+                     </map>\n";
+        assert_eq!(
+            Some(String::from(
+                "<map id=\"bar\" name=\"bar\">\n\
+                 <area shape=\"rect\" id=\"id1\" href=\"img/foo.html\" coords=\"1,2,3,4\"/>\n\
+                 </map>"
+            )),
+            rename_image_map(cmapx, "bar", "img")
+        );
 
-        self.cleaner.borrow_mut().keep(&output_file);
-        let extension = output_file.extension().unwrap_or_default();
-        if extension == "atxt" || extension == "utxt" {
-            Self::create_inline_image(&output_file)
-        } else {
-            Self::create_md_link(rel_img_url, &output_file, self.clickable_img)
-        }
+        assert_eq!(None, rename_image_map("", "bar", "img"));
+        assert_eq!(
+            None,
+            rename_image_map(
+                "<map id=\"plantuml_map\" name=\"plantuml_map\">\n</map>",
+                "bar",
+                "img"
+            )
+        );
     }
-}
 
-impl PlantUMLRendererTrait for PlantUMLRenderer {
-    fn render(&self, plantuml_code: &str, rel_img_url: &str, image_format: String) -> String {
-        Self::render(self, plantuml_code, rel_img_url, &image_format)
+    #[test]
+    fn test_rebase_relative_hrefs() {
+        assert_eq!(
+            "<area href=\"../img/../foo.html\"/><area href=\"https://x.org/\"/>",
+            rebase_relative_hrefs(
+                "<area href=\"../foo.html\"/><area href=\"https://x.org/\"/>",
+                "../img"
+            )
+        );
+        assert_eq!(
+            "<area href=\"/foo.html\" title=\"#\"/><area href=\"#bar\"/>",
+            rebase_relative_hrefs(
+                "<area href=\"/foo.html\" title=\"#\"/><area href=\"#bar\"/>",
+                "img"
+            )
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::{bail, Result};
-    use pretty_assertions::assert_eq;
-    use tempfile::tempdir;
+    #[test]
+    fn test_inject_directive() {
+        assert_eq!(
+            "@startuml\nscale 1.5\nA --|> B\n@enduml\n",
+            inject_directive("@startuml\nA --|> B\n@enduml\n", "scale 1.5", None)
+        );
+        assert_eq!(
+            "@startuml\nscale 2\nA\n@enduml\n  @startditaa(scale=2)\nB\n@enduml",
+            inject_directive(
+                "@startuml\nA\n@enduml\n  @startditaa\nB\n@enduml",
+                "scale 2",
+                Some("scale=2")
+            )
+        );
+        // ditaa diagrams are left alone when there is no ditaa option
+        assert_eq!(
+            "@startditaa\nB\n@enduml",
+            inject_directive("@startditaa\nB\n@enduml", "!theme cyborg", None)
+        );
+        assert_eq!(
+            "@startuml\n!theme cyborg\nskinparam backgroundColor black\n",
+            inject_directive(
+                "@startuml",
+                "!theme cyborg\nskinparam backgroundColor black",
+                None
+            )
+        );
+        assert_eq!(
+            "scale 2\nA --|> B",
+            inject_directive("A --|> B", "scale 2", None)
+        );
+    }
 
     #[test]
-    fn test_create_md_link() {
+    fn test_get_ditaa_scale_option() {
+        let code = "@startditaa\nB\n@enduml";
         assert_eq!(
-            String::from("![](foo/bar/baz.svg)\n\n"),
-            PlantUMLRenderer::create_md_link("foo/bar", Path::new("/froboz/baz.svg"), false)
+            Some(String::from("scale=1.5")),
+            get_ditaa_scale_option(code, " 1.5")
         );
+        assert_eq!(None, get_ditaa_scale_option(code, "2/3"));
+        assert_eq!(None, get_ditaa_scale_option(code, "max 300*200"));
+        assert_eq!(None, get_ditaa_scale_option(code, "-1"));
 
+        // Non-numeric scales still apply to regular diagrams
+        let code = "@startuml\nA\n@enduml\n@startditaa\nB\n@enduml";
         assert_eq!(
-            "![](/baz.svg)\n\n",
-            PlantUMLRenderer::create_md_link("", Path::new("baz.svg"), false)
+            "@startuml\nscale 2/3\nA\n@enduml\n@startditaa\nB\n@enduml",
+            inject_directive(
+                code,
+                "scale 2/3",
+                get_ditaa_scale_option(code, "2/3").as_deref()
+            )
         );
+    }
 
+    #[test]
+    fn test_escape_html_attribute() {
+        assert_eq!("foo", escape_html_attribute("foo"));
         assert_eq!(
-            String::from("![](/baz.svg)\n\n"),
-            PlantUMLRenderer::create_md_link("", Path::new("foo/baz.svg"), false)
+            "&lt;a href=&quot;x&amp;y&quot;&gt;",
+            escape_html_attribute("<a href=\"x&y\">")
         );
     }
 
@@ -152,57 +1357,592 @@ mod tests {
             }
             bail!("Oh no")
         }
+
+        fn check_syntax(&self, _plantuml_code: &str) -> Result<Option<SyntaxError>> {
+            Ok(None)
+        }
     }
 
     #[test]
     fn test_rendering() {
         let output_dir = tempdir().unwrap();
-        let renderer = PlantUMLRenderer {
-            backend: Box::new(BackendMock { is_ok: true }),
-            cleaner: RefCell::new(DirCleaner::new(output_dir.path())),
-            img_root: output_dir.path().to_path_buf(),
-            clickable_img: false,
-        };
+        let renderer = create_test_renderer(PlantUMLConfig::default(), output_dir.path(), true);
 
         let plantuml_code = "some puml code";
         let code_hash = hash_string(plantuml_code);
 
         assert_eq!(
             format!("![](rel/url/{}.svg)\n\n", code_hash),
-            renderer.render(plantuml_code, "rel/url", "svg",)
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
         );
 
         // png extension
         assert_eq!(
             format!("![](rel/url/{}.png)\n\n", code_hash),
-            renderer.render(plantuml_code, "rel/url", "png",)
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "png",
+                &DiagramAttributes::default()
+            )
         );
 
         // txt extension
         assert_eq!(
             format!("\n```txt\n{}\ntxt```\n", plantuml_code), /* image format is appended by
                                                                * fake backend */
-            renderer.render(plantuml_code, "rel/url", "txt",)
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "txt",
+                &DiagramAttributes::default()
+            )
         );
 
         // utxt extension
         assert_eq!(
             format!("\n```txt\n{}\ntxt```\n", plantuml_code), /* image format is appended by
                                                                * fake backend */
-            renderer.render(plantuml_code, "rel/url", "txt",)
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "txt",
+                &DiagramAttributes::default()
+            )
         );
     }
 
     #[test]
-    fn test_rendering_clickable() {
+    fn test_rendering_adds_code_blocks_to_the_report() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(PlantUMLConfig::default(), output_dir.path(), true);
+        renderer.start_chapter(Path::new("chapter.md"));
+        let attributes = DiagramAttributes {
+            line: Some(5),
+            ..DiagramAttributes::default()
+        };
+        renderer.render("some puml code", "rel/url", "svg", &attributes);
+        renderer.render("some puml code", "rel/url", "svg", &attributes);
+        // Diagrams without a line (e.g. figure list thumbnails) are not added
+        renderer.render(
+            "some puml code",
+            "rel/url",
+            "svg",
+            &DiagramAttributes::default(),
+        );
+
+        let failing_renderer =
+            create_test_renderer(PlantUMLConfig::default(), output_dir.path(), false);
+        failing_renderer.render("other puml code", "rel/url", "png", &attributes);
+
+        let report = renderer.report.borrow();
+        let totals = report.get_totals();
+        assert_eq!(
+            (2, 1, 1, 0),
+            (
+                totals.diagrams,
+                totals.cache_hits,
+                totals.cache_misses,
+                totals.errors
+            )
+        );
+        // The mock writes the code and format to the image file
+        assert_eq!(2 * "some puml code\nsvg".len() as u64, totals.output_size);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!("chapter.md", json["diagrams"][0]["chapter"]);
+        assert_eq!(5, json["diagrams"][0]["line"]);
+        assert_eq!(hash_string("some puml code"), json["diagrams"][0]["hash"]);
+        assert_eq!("svg", json["diagrams"][0]["format"]);
+        assert_eq!(false, json["diagrams"][0]["cache_hit"]);
+        assert_eq!(true, json["diagrams"][1]["cache_hit"]);
+
+        let failing_report = failing_renderer.report.borrow();
+        assert_eq!(1, failing_report.get_totals().errors);
+        let json: serde_json::Value =
+            serde_json::from_str(&failing_report.to_json().unwrap()).unwrap();
+        assert_eq!("Oh no", json["diagrams"][0]["error"]);
+        assert_eq!(serde_json::Value::Null, json["diagrams"][0]["chapter"]);
+    }
+
+    struct MultiPageBackendMock;
+
+    impl PlantUMLBackend for MultiPageBackendMock {
+        fn render_from_string(
+            &self,
+            plantuml_code: &str,
+            _image_format: &str,
+            output_file: &Path,
+        ) -> Result<()> {
+            for page in 0..count_pages(plantuml_code) {
+                std::fs::write(get_page_filename(output_file, page), "")?;
+            }
+            Ok(())
+        }
+
+        fn check_syntax(&self, _plantuml_code: &str) -> Result<Option<SyntaxError>> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_rendering_multiple_pages() {
+        let output_dir = tempdir().unwrap();
+        let renderer = PlantUMLRenderer::with_backend(
+            &PlantUMLConfig::default(),
+            output_dir.path(),
+            Box::new(MultiPageBackendMock {}),
+        );
+
+        let plantuml_code = "@startuml\nA\nnewpage\nB\n@enduml\n@startuml\nC\n@enduml";
+        let code_hash = hash_string(plantuml_code);
+        let expected = format!(
+            "![](rel/url/{hash}.svg)\n\n![](rel/url/{hash}_001.svg)\n\n![](rel/url/{hash}_002.svg)\n\n",
+            hash = code_hash
+        );
+
+        assert_eq!(
+            expected,
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+        // Cached pages are detected too
+        assert_eq!(
+            expected,
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rendering_with_attributes() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(PlantUMLConfig::default(), output_dir.path(), true);
+
+        let attributes = DiagramAttributes {
+            width: Some(String::from("200")),
+            scale: Some(String::from("2")),
+            ..DiagramAttributes::default()
+        };
+        let plantuml_code = "@startuml\nA --|> B\n@enduml";
+        // The scale directive is passed to PlantUML, so it is part of the hash
+        let code_hash = hash_string("@startuml\nscale 2\nA --|> B\n@enduml");
+        assert_eq!(
+            format!(
+                "<img src=\"rel/url/{}.svg\" alt=\"\" width=\"200\">\n\n",
+                code_hash
+            ),
+            renderer.render(plantuml_code, "rel/url", "svg", &attributes)
+        );
+
+        let rendered_source =
+            fs::read_to_string(output_dir.path().join(format!("{}.svg", code_hash))).unwrap();
+        assert_eq!(
+            "@startuml\nscale 2\nA --|> B\n@enduml\nsvg",
+            rendered_source
+        );
+    }
+
+    #[test]
+    fn test_rendering_figure() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(PlantUMLConfig::default(), output_dir.path(), true);
+
+        let plantuml_code = "@startuml\nA --|> B\n@enduml";
+        let mut attributes = DiagramAttributes {
+            align: Some(String::from("center")),
+            id: Some(String::from("storage-layout")),
+            caption: Some(String::from("Storage <layout>")),
+            ..DiagramAttributes::default()
+        };
+        let mut figures = FigureIndex::new(FigureNumbering::Chapter);
+        figures.start_chapter(Path::new("storage.md"), Some(&[3]));
+        attributes.figure = Some(
+            figures
+                .add_figure(plantuml_code, "svg", &attributes)
+                .unwrap(),
+        );
+        assert_eq!(
+            format!(
+                "<figure id=\"storage-layout\" class=\"mdbook-plantuml-figure\" \
+                 style=\"text-align: center;\">\n\n\
+                 <img src=\"rel/url/{}.svg\" alt=\"\">\n\n\
+                 <figcaption>Figure 3.1: Storage &lt;layout&gt;</figcaption>\n</figure>\n\n",
+                hash_string(plantuml_code)
+            ),
+            renderer.render(plantuml_code, "rel/url", "svg", &attributes)
+        );
+    }
+
+    #[test]
+    fn test_rendering_searchable_text() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                searchable_text: true,
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
+        );
+
+        // The mock backend writes the source to the image file, so the svg
+        // labels are the <text> elements in the source
+        let plantuml_code = "<text x=\"1\">Storage</text>";
+        assert_eq!(
+            format!(
+                "![](rel/url/{}.svg)\n\n\
+                 <div class=\"mdbook-plantuml-text\" style=\"{}\">Storage</div>\n\n",
+                hash_string(plantuml_code),
+                VISUALLY_HIDDEN_STYLE
+            ),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+
+        // Other formats use the words in the source
+        let plantuml_code = "@startuml\ncomponent Storage\n@enduml";
+        assert_eq!(
+            format!(
+                "![](rel/url/{}.png)\n\n\
+                 <div class=\"mdbook-plantuml-text\" style=\"{}\">Storage</div>\n\n",
+                hash_string(plantuml_code),
+                VISUALLY_HIDDEN_STYLE
+            ),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "png",
+                &DiagramAttributes::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rendering_postprocesses_svg() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                svg_strip_comments: true,
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
+        );
+
+        let plantuml_code = "<svg><!--A --|> B--></svg>";
+        renderer.render(
+            plantuml_code,
+            "rel/url",
+            "svg",
+            &DiagramAttributes::default(),
+        );
+        let image_file = output_dir
+            .path()
+            .join(format!("{}.svg", hash_string(plantuml_code)));
+        assert_eq!("<svg></svg>\nsvg", fs::read_to_string(image_file).unwrap());
+    }
+
+    #[test]
+    fn test_lightbox_assets() {
+        let output_dir = tempdir().unwrap();
+        let cfg = PlantUMLConfig {
+            plantuml_cmd: Some(String::from("plantuml")),
+            zoom: Some(ZoomMode::Lightbox),
+            ..PlantUMLConfig::default()
+        };
+        let renderer = PlantUMLRenderer::new(&cfg, output_dir.path());
+        renderer.write_lightbox_assets();
+
+        assert_eq!(
+            "<link rel=\"stylesheet\" href=\"rel/url/mdbook-plantuml-lightbox.css\">\n\
+             <script src=\"rel/url/mdbook-plantuml-lightbox.js\"></script>\n",
+            renderer.get_chapter_assets("rel/url")
+        );
+
+        // The assets are written to, and kept in the image dir
+        drop(renderer);
+        let js_file = output_dir.path().join("mdbook-plantuml-lightbox.js");
+        assert_eq!(LIGHTBOX_JS.1, fs::read_to_string(js_file).unwrap());
+        assert!(output_dir
+            .path()
+            .join("mdbook-plantuml-lightbox.css")
+            .exists());
+    }
+
+    #[test]
+    fn test_rendering_lazy_loading() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                lazy_loading: true,
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
+        );
+
+        // The mock backend writes the source to the image file
+        let plantuml_code = "<svg viewBox=\"0 0 300 120\"/>";
+        assert_eq!(
+            format!(
+                "<img src=\"rel/url/{}.svg\" alt=\"\" loading=\"lazy\" decoding=\"async\" \
+                 width=\"300\" height=\"120\" style=\"height: auto;\">\n\n",
+                hash_string(plantuml_code)
+            ),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+
+        // An explicit size takes precedence
+        let attributes = DiagramAttributes {
+            width: Some(String::from("50%")),
+            ..DiagramAttributes::default()
+        };
+        assert_eq!(
+            format!(
+                "<img src=\"rel/url/{}.svg\" alt=\"\" width=\"50%\" loading=\"lazy\" \
+                 decoding=\"async\">\n\n",
+                hash_string(plantuml_code)
+            ),
+            renderer.render(plantuml_code, "rel/url", "svg", &attributes)
+        );
+    }
+
+    #[test]
+    fn test_rendering_hidpi() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                hidpi_png: true,
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
+        );
+
+        let plantuml_code = "@startuml\nA --|> B\n@enduml";
+        let code_hash = hash_string(plantuml_code);
+        assert_eq!(
+            format!(
+                "<img src=\"rel/url/{hash}.png\" srcset=\"rel/url/{hash}.png 1x, rel/url/{hash}@2x.png 2x\" alt=\"\">\n\n",
+                hash = code_hash
+            ),
+            renderer.render(plantuml_code, "rel/url", "png", &DiagramAttributes::default())
+        );
+        let hidpi_source =
+            fs::read_to_string(output_dir.path().join(format!("{}@2x.png", code_hash))).unwrap();
+        assert_eq!(
+            "@startuml\nskinparam dpi 192\nA --|> B\n@enduml\npng",
+            hidpi_source
+        );
+
+        // The scale of scaled ditaa diagrams is doubled
+        let ditaa_code = "@startditaa\n+--+\n@endditaa";
+        let attributes = DiagramAttributes {
+            scale: Some(String::from("1.5")),
+            ..DiagramAttributes::default()
+        };
+        let scaled_hash = hash_string("@startditaa(scale=1.5)\n+--+\n@endditaa");
+        assert!(renderer
+            .render(ditaa_code, "rel/url", "png", &attributes)
+            .contains(&format!("rel/url/{}@2x.png 2x", scaled_hash)));
+        let hidpi_source =
+            fs::read_to_string(output_dir.path().join(format!("{}@2x.png", scaled_hash))).unwrap();
+        assert_eq!("@startditaa(scale=3)\n+--+\n@endditaa\npng", hidpi_source);
+
+        // Only png images get a HiDPI variant
+        assert_eq!(
+            format!("![](rel/url/{}.svg)\n\n", code_hash),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rendering_dark_variant() {
         let output_dir = tempdir().unwrap();
-        let renderer = PlantUMLRenderer {
-            backend: Box::new(BackendMock { is_ok: true }),
-            cleaner: RefCell::new(DirCleaner::new(output_dir.path())),
-            img_root: output_dir.path().to_path_buf(),
-            clickable_img: true,
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                dark_theme: Some(String::from("cyborg")),
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
+        );
+
+        let plantuml_code = "@startuml\nA --|> B\n@enduml";
+        let dark_code = "@startuml\n!theme cyborg\nA --|> B\n@enduml";
+        assert_eq!(
+            format!(
+                "<img src=\"rel/url/{}.svg\" alt=\"\" class=\"mdbook-plantuml-light\">\
+                 <img src=\"rel/url/{}.svg\" alt=\"\" class=\"mdbook-plantuml-dark\">\n\n",
+                hash_string(plantuml_code),
+                hash_string(dark_code)
+            ),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+        assert!(renderer
+            .get_chapter_assets("rel/url")
+            .contains("html.coal .mdbook-plantuml-dark"));
+
+        // No dark variant for ditaa diagrams
+        let ditaa_code = "@startditaa\n+--+\n@endditaa";
+        assert_eq!(
+            format!("![](rel/url/{}.png)\n\n", hash_string(ditaa_code)),
+            renderer.render(ditaa_code, "rel/url", "png", &DiagramAttributes::default())
+        );
+    }
+
+    #[test]
+    fn test_get_dark_preamble() {
+        let create_renderer = |dark_theme: Option<&str>, dark_preamble: Option<&str>| {
+            let output_dir = tempdir().unwrap();
+            create_test_renderer(
+                PlantUMLConfig {
+                    dark_theme: dark_theme.map(String::from),
+                    dark_preamble: dark_preamble.map(String::from),
+                    ..PlantUMLConfig::default()
+                },
+                output_dir.path(),
+                true,
+            )
         };
 
+        assert_eq!(None, create_renderer(None, None).get_dark_preamble());
+        assert_eq!(
+            String::new(),
+            create_renderer(None, None).get_chapter_assets("rel/url")
+        );
+        assert_eq!(
+            Some(String::from("!theme cyborg")),
+            create_renderer(Some("cyborg"), None).get_dark_preamble()
+        );
+        assert_eq!(
+            Some(String::from("skinparam backgroundColor black")),
+            create_renderer(None, Some("skinparam backgroundColor black")).get_dark_preamble()
+        );
+        assert_eq!(
+            Some(String::from(
+                "!theme cyborg\nskinparam backgroundColor black"
+            )),
+            create_renderer(Some("cyborg"), Some("skinparam backgroundColor black"))
+                .get_dark_preamble()
+        );
+    }
+
+    /// Backend rendering a client side image map for the map format
+    struct ImageMapBackendMock;
+
+    impl PlantUMLBackend for ImageMapBackendMock {
+        fn render_from_string(
+            &self,
+            plantuml_code: &str,
+            image_format: &str,
+            output_file: &Path,
+        ) -> Result<()> {
+            if image_format == IMAGE_MAP_FORMAT {
+                std::fs::write(
+                    output_file,
+                    "<map id=\"plantuml_map\" name=\"plantuml_map\">\n\
+                     <area shape=\"rect\" href=\"foo.html\" coords=\"1,2,3,4\"/>\n</map>\n",
+                )?;
+            } else {
+                std::fs::write(output_file, plantuml_code)?;
+            }
+            Ok(())
+        }
+
+        fn check_syntax(&self, _plantuml_code: &str) -> Result<Option<SyntaxError>> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_rendering_image_map() {
+        let output_dir = tempdir().unwrap();
+        let renderer = PlantUMLRenderer::with_backend(
+            &PlantUMLConfig::default(),
+            output_dir.path(),
+            Box::new(ImageMapBackendMock {}),
+        );
+
+        let plantuml_code = "@startuml\nclass A [[foo.html]]\n@enduml";
+        let code_hash = hash_string(plantuml_code);
+        assert_eq!(
+            format!(
+                "<img src=\"rel/url/{hash}.png\" alt=\"\" usemap=\"#mdbook-plantuml-{hash}\">\n\
+                 <map id=\"mdbook-plantuml-{hash}\" name=\"mdbook-plantuml-{hash}\">\n\
+                 <area shape=\"rect\" href=\"rel/url/foo.html\" coords=\"1,2,3,4\"/>\n\
+                 </map>\n\n",
+                hash = code_hash
+            ),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "png",
+                &DiagramAttributes::default()
+            )
+        );
+        assert!(output_dir
+            .path()
+            .join(format!("{}.cmapx", code_hash))
+            .exists());
+
+        // No image maps for svg diagrams (they support links natively)
+        assert_eq!(
+            format!("![](rel/url/{}.svg)\n\n", code_hash),
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rendering_clickable() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                clickable_img: true,
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
+        );
+
         let plantuml_code = "some puml code";
         let code_hash = hash_string(plantuml_code);
         assert_eq!(
@@ -210,23 +1950,75 @@ mod tests {
                 "[![](rel/url/{}.svg)](rel/url/{}.svg)\n\n",
                 code_hash, code_hash
             ),
-            renderer.render(plantuml_code, "rel/url", "svg")
+            renderer.render(
+                plantuml_code,
+                "rel/url",
+                "svg",
+                &DiagramAttributes::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_source() {
+        let create_renderer = |show_source: bool, plantuml_cmd: Option<&str>| {
+            let output_dir = tempdir().unwrap();
+            create_test_renderer(
+                PlantUMLConfig {
+                    show_source,
+                    plantuml_cmd: plantuml_cmd.map(String::from),
+                    ..PlantUMLConfig::default()
+                },
+                output_dir.path(),
+                true,
+            )
+        };
+
+        assert_eq!(
+            String::new(),
+            create_renderer(false, None).render_source("C --|> D\n")
+        );
+        assert_eq!(
+            "\n<details class=\"mdbook-plantuml-source\">\n\
+             <summary>PlantUML source</summary>\n\n\
+             ```plantuml\nC --|> D\n```\n\n\
+             </details>\n\n",
+            create_renderer(true, Some("plantuml")).render_source("C --|> D\n")
+        );
+
+        // Backticks in the code get a longer fence
+        let source = create_renderer(true, None).render_source("note: ````");
+        assert!(source.contains("\n`````plantuml\nnote: ````\n`````\n"));
+    }
+
+    #[cfg(any(feature = "plantuml-ssl-server", feature = "plantuml-server"))]
+    #[test]
+    fn test_render_source_with_editor_link() {
+        let output_dir = tempdir().unwrap();
+        let renderer = create_test_renderer(
+            PlantUMLConfig {
+                show_source: true,
+                plantuml_cmd: Some(String::from("http://froboz:1234/plantuml")),
+                ..PlantUMLConfig::default()
+            },
+            output_dir.path(),
+            true,
         );
+
+        assert!(renderer.render_source("C --|> D").contains(
+            "<p><a href=\"http://froboz:1234/plantuml/uml/SrRGrQsnKt0100==\" \
+             target=\"_blank\">Open in PlantUML editor</a></p>\n\n</details>"
+        ));
     }
 
     #[test]
     fn test_rendering_failure() {
         let output_dir = tempdir().unwrap();
-        let renderer = PlantUMLRenderer {
-            backend: Box::new(BackendMock { is_ok: false }),
-            cleaner: RefCell::new(DirCleaner::new(output_dir.path())),
-            img_root: output_dir.path().to_path_buf(),
-            clickable_img: false,
-        };
+        let renderer = create_test_renderer(PlantUMLConfig::default(), output_dir.path(), false);
 
         assert_eq!(
             String::from("\nPlantUML rendering error:\nOh no\n\n"),
-            renderer.render("", "rel/url", "svg",)
+            renderer.render("", "rel/url", "svg", &DiagramAttributes::default())
         );
     }
 
@@ -252,6 +2044,11 @@ mod tests {
 
         assert_eq!(String::from("atxt"), get_extension_from_filename("", "txt"));
 
+        assert_eq!(
+            String::from("cmapx"),
+            get_extension_from_filename("", IMAGE_MAP_FORMAT)
+        );
+
         // Plantuml does this 'braille.png' extension
         assert_eq!(
             String::from("braille.png"),
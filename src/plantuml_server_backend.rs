@@ -1,7 +1,8 @@
 use crate::base64_plantuml;
-use crate::plantuml_backend::PlantUMLBackend;
-use anyhow::{bail, Result};
+use crate::plantuml_backend::{count_pages, get_page_filename, PlantUMLBackend, SyntaxError};
+use anyhow::{bail, format_err, Context, Result};
 use deflate::deflate_bytes;
+use inflate::inflate_bytes;
 use reqwest::Url;
 use std::fs;
 use std::io::prelude::*;
@@ -47,8 +48,14 @@ impl PlantUMLServer {
     }
 
     /// Format the PlantUML server URL using the encoded diagram and extension
-    fn get_url(&self, image_format: &str, encoded_diagram: &str) -> Result<Url> {
-        let path = format!("{}/{}", image_format, encoded_diagram);
+    /// Pages other than the first one use the page index URL form
+    /// (`<format>/<page>/<encoded diagram>`).
+    fn get_url(&self, image_format: &str, page: usize, encoded_diagram: &str) -> Result<Url> {
+        let path = if page == 0 {
+            format!("{}/{}", image_format, encoded_diagram)
+        } else {
+            format!("{}/{}/{}", image_format, page, encoded_diagram)
+        };
 
         self.server_url.join(&path).map_err(|e| {
             anyhow::format_err!(
@@ -60,9 +67,22 @@ impl PlantUMLServer {
         })
     }
 
+    /// Get the URL for opening the diagram in the server's online editor
+    pub fn get_editor_url(&self, plantuml_code: &str) -> Result<Url> {
+        let path = format!("uml/{}", encode_diagram_source(plantuml_code));
+        self.server_url.join(&path).map_err(|e| {
+            anyhow::format_err!(
+                "Error constructing PlantUML editor URL from '{}' and '{}' ({})",
+                self.server_url.as_str(),
+                path,
+                e
+            )
+        })
+    }
+
     /// Save the downloaded image to a file
     fn save_downloaded_image(image_buffer: &[u8], file_path: &Path) -> Result<()> {
-        let mut output_file = fs::File::create(&file_path)?;
+        let mut output_file = fs::File::create(file_path)?;
         output_file.write_all(image_buffer)?;
 
         Ok(())
@@ -78,12 +98,40 @@ impl PlantUMLServer {
         downloader: &dyn ImageDownloader,
     ) -> Result<()> {
         let encoded = encode_diagram_source(plantuml_code);
-        let request_url = self.get_url(image_format, &encoded)?;
-        let image_buffer = downloader.download_image(&request_url)?;
-        Self::save_downloaded_image(&image_buffer, output_file)?;
+        for page in 0..count_pages(plantuml_code) {
+            let request_url = self.get_url(image_format, page, &encoded)?;
+            let image_buffer = downloader.download_image(&request_url)?;
+            Self::save_downloaded_image(&image_buffer, &get_page_filename(output_file, page))?;
+        }
 
         Ok(())
     }
+
+    /// Check the diagram syntax using the server's check endpoint, which
+    /// responds with the diagram description (`(Error)` for invalid diagrams).
+    /// The server does not report the line of the error.
+    fn check_string(
+        &self,
+        plantuml_code: &str,
+        downloader: &dyn ImageDownloader,
+    ) -> Result<Option<SyntaxError>> {
+        let request_url = self.get_url("check", 0, &encode_diagram_source(plantuml_code))?;
+        let response = downloader.download_image(&request_url)?;
+        let description = String::from_utf8_lossy(&response);
+        let message = match description.trim().strip_prefix("(Error)") {
+            Some(message) => message.trim(),
+            None => return Ok(None),
+        };
+
+        Ok(Some(SyntaxError {
+            line: None,
+            message: if message.is_empty() {
+                String::from("Syntax Error?")
+            } else {
+                String::from(message)
+            },
+        }))
+    }
 }
 
 /// Compress and encode the image source, return the encoed Base64-ish string
@@ -92,6 +140,59 @@ fn encode_diagram_source(plantuml_code: &str) -> String {
     base64_plantuml::encode(&compressed)
 }
 
+/// Decode an encoded diagram source, either compressed and Base64-ish encoded
+/// or in the hex form (`~h` followed by the hex encoded source)
+fn decode_diagram_source(encoded: &str) -> Result<String> {
+    let source = match encoded.strip_prefix("~h") {
+        Some(hex) => base16ct::mixed::decode_vec(hex)
+            .map_err(|e| format_err!("Invalid hex encoded diagram ({})", e))?,
+        None => inflate_bytes(&base64_plantuml::decode(encoded)?)
+            .map_err(|e| format_err!("Failed to decompress the diagram ({})", e))?,
+    };
+
+    String::from_utf8(source).context("The decoded diagram is not valid UTF-8")
+}
+
+/// Encode a diagram source for use in PlantUML server URLs. Returns the URL of
+/// the diagram image on `server_url` if given, or just the encoded diagram.
+pub fn encode_diagram(
+    plantuml_code: &str,
+    server_url: Option<&str>,
+    image_format: &str,
+) -> Result<String> {
+    let encoded = encode_diagram_source(plantuml_code);
+    match server_url {
+        Some(server_url) => {
+            let server_url = Url::parse(server_url)
+                .with_context(|| format!("Invalid server URL '{}'", server_url))?;
+            let url = PlantUMLServer::new(server_url).get_url(image_format, 0, &encoded)?;
+            Ok(url.to_string())
+        }
+        None => Ok(encoded),
+    }
+}
+
+/// Decode the diagram source from a PlantUML server URL (e.g.
+/// `http://localhost:8080/svg/SrRGrQsnKt0100`) or an encoded diagram
+pub fn decode_diagram(url_or_encoded: &str) -> Result<String> {
+    // The encoded diagram is the last segment of the URL path
+    let path = url_or_encoded
+        .trim()
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .unwrap_or_default();
+    let encoded = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    if encoded.is_empty() {
+        bail!("No encoded diagram found in '{}'", url_or_encoded);
+    }
+
+    decode_diagram_source(encoded).with_context(|| format!("Failed to decode '{}'", url_or_encoded))
+}
+
 impl PlantUMLBackend for PlantUMLServer {
     fn render_from_string(
         &self,
@@ -102,6 +203,11 @@ impl PlantUMLBackend for PlantUMLServer {
         let downloader = RealImageDownloader {};
         self.render_string(plantuml_code, output_file, image_format, &downloader)
     }
+
+    fn check_syntax(&self, plantuml_code: &str) -> Result<Option<SyntaxError>> {
+        let downloader = RealImageDownloader {};
+        self.check_string(plantuml_code, &downloader)
+    }
 }
 
 #[cfg(test)]
@@ -111,6 +217,7 @@ mod tests {
     use anyhow::Result;
     use pretty_assertions::assert_eq;
     use simulacrum::*;
+    use std::cell::RefCell;
     use tempfile::tempdir;
 
     #[test]
@@ -119,7 +226,12 @@ mod tests {
 
         assert_eq!(
             Url::parse("http://froboz:1234/plantuml/ext/plantuml_encoded_string").unwrap(),
-            srv.get_url("ext", "plantuml_encoded_string").unwrap()
+            srv.get_url("ext", 0, "plantuml_encoded_string").unwrap()
+        );
+
+        assert_eq!(
+            Url::parse("http://froboz:1234/plantuml/ext/2/plantuml_encoded_string").unwrap(),
+            srv.get_url("ext", 2, "plantuml_encoded_string").unwrap()
         );
 
         // I cannot manage Url::parse to fail using the ext and encoded data
@@ -133,7 +245,17 @@ mod tests {
 
         assert_eq!(
             Url::parse("http://froboz:1234/ext/plantuml_encoded_string").unwrap(),
-            srv.get_url("ext", "plantuml_encoded_string").unwrap()
+            srv.get_url("ext", 0, "plantuml_encoded_string").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_editor_url() {
+        let srv = PlantUMLServer::new(Url::parse("http://froboz:1234/plantuml").unwrap());
+
+        assert_eq!(
+            Url::parse("http://froboz:1234/plantuml/uml/SrRGrQsnKt0100==").unwrap(),
+            srv.get_editor_url("C --|> D").unwrap()
         );
     }
 
@@ -142,6 +264,45 @@ mod tests {
         assert_eq!("SrRGrQsnKt0100==", encode_diagram_source("C --|> D"));
     }
 
+    #[test]
+    fn test_encode_diagram() {
+        assert_eq!(
+            "SrRGrQsnKt0100==",
+            encode_diagram("C --|> D", None, "svg").unwrap()
+        );
+        assert_eq!(
+            "http://froboz:1234/plantuml/png/SrRGrQsnKt0100==",
+            encode_diagram("C --|> D", Some("http://froboz:1234/plantuml"), "png").unwrap()
+        );
+        assert!(encode_diagram("C --|> D", Some("froboz"), "svg").is_err());
+    }
+
+    #[test]
+    fn test_decode_diagram() {
+        let code = "@startuml\nAlice -> Bob: Hello ☺\n@enduml\n";
+        let encoded = encode_diagram_source(code);
+        assert_eq!(code, decode_diagram(&encoded).unwrap());
+        assert_eq!(code, decode_diagram(encoded.trim_end_matches('=')).unwrap());
+        assert_eq!(
+            "C --|> D",
+            decode_diagram("http://froboz:1234/plantuml/svg/SrRGrQsnKt0100==").unwrap()
+        );
+        assert_eq!(
+            "C --|> D",
+            decode_diagram("https://froboz/plantuml/png/1/SrRGrQsnKt0100?x=1").unwrap()
+        );
+
+        // Hex form
+        assert_eq!(
+            "A -> B",
+            decode_diagram("http://froboz/uml/~h41202d3e2042").unwrap()
+        );
+
+        assert!(decode_diagram("http://froboz/svg/").is_err());
+        assert!(decode_diagram("~hxyz").is_err());
+        assert!(decode_diagram("not encoded!").is_err());
+    }
+
     #[test]
     fn test_save_downloaded_image() {
         let tmp_dir = tempdir().unwrap();
@@ -183,4 +344,80 @@ mod tests {
         let raw_source = fs::read(output_file).unwrap();
         assert_eq!("the rendered image", String::from_utf8_lossy(&raw_source));
     }
+
+    struct FakeImageDownloader {
+        requested_urls: RefCell<Vec<Url>>,
+    }
+
+    impl ImageDownloader for FakeImageDownloader {
+        fn download_image(&self, request_url: &Url) -> Result<Vec<u8>> {
+            self.requested_urls.borrow_mut().push(request_url.clone());
+            Ok(request_url.as_str().as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn test_render_string_multiple_pages() {
+        let tmp_dir = tempdir().unwrap();
+        let srv = PlantUMLServer::new(Url::parse("http://froboz").unwrap());
+        let output_file = join_path(tmp_dir.path(), "foobar.svg");
+        let downloader = FakeImageDownloader {
+            requested_urls: RefCell::new(Vec::new()),
+        };
+
+        let code = "@startuml\nA --|> B\nnewpage\nB --|> C\n@enduml";
+        srv.render_string(code, &output_file, "svg", &downloader)
+            .unwrap();
+
+        let encoded = encode_diagram_source(code);
+        let expected_urls = vec![
+            Url::parse(&format!("http://froboz/svg/{}", encoded)).unwrap(),
+            Url::parse(&format!("http://froboz/svg/1/{}", encoded)).unwrap(),
+        ];
+        assert_eq!(expected_urls, *downloader.requested_urls.borrow());
+
+        for (page, url) in expected_urls.iter().enumerate() {
+            let raw_source = fs::read(get_page_filename(&output_file, page)).unwrap();
+            assert_eq!(url.as_str(), String::from_utf8_lossy(&raw_source));
+        }
+    }
+
+    struct CheckDownloader {
+        description: &'static str,
+    }
+
+    impl ImageDownloader for CheckDownloader {
+        fn download_image(&self, request_url: &Url) -> Result<Vec<u8>> {
+            assert!(request_url.as_str().starts_with("http://froboz/check/"));
+            Ok(self.description.as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn test_check_string() {
+        let srv = PlantUMLServer::new(Url::parse("http://froboz").unwrap());
+        let code = "@startuml\nA --|> B\n@enduml";
+
+        let downloader = CheckDownloader {
+            description: "(2 entities)",
+        };
+        assert_eq!(None, srv.check_string(code, &downloader).unwrap());
+
+        // Only the error marker counts, not "error" in the description
+        let downloader = CheckDownloader {
+            description: "(1 entities) ErrorHandler",
+        };
+        assert_eq!(None, srv.check_string(code, &downloader).unwrap());
+
+        let downloader = CheckDownloader {
+            description: "(Error)\n",
+        };
+        assert_eq!(
+            Some(SyntaxError {
+                line: None,
+                message: String::from("Syntax Error?"),
+            }),
+            srv.check_string(code, &downloader).unwrap()
+        );
+    }
 }
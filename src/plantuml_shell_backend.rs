@@ -4,7 +4,9 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 
-use crate::plantuml_backend::PlantUMLBackend;
+use crate::plantuml_backend::{
+    get_existing_pages, get_page_filename, PlantUMLBackend, SyntaxError, IMAGE_MAP_FORMAT,
+};
 use anyhow::{bail, Result};
 use tempfile::{tempdir, TempDir};
 
@@ -14,27 +16,53 @@ use tempfile::{tempdir, TempDir};
 /// integration tests
 trait CommandExecutor {
     fn execute(&self, args: &[String]) -> Result<()>;
+
+    /// Execute the command and return its standard output
+    fn output(&self, args: &[String]) -> Result<String>;
+}
+
+/// Create the command for running a command line through the shell (the
+/// command line is the argument to add)
+pub fn create_shell_command() -> Command {
+    if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    }
 }
 
-struct RealCommandExecutor;
+struct RealCommandExecutor<'a> {
+    /// The dir the command is run in, the current dir if None
+    working_dir: Option<&'a Path>,
+}
+
+impl RealCommandExecutor<'_> {
+    fn create_command(&self) -> Command {
+        let mut cmd = create_shell_command();
+        if let Some(working_dir) = self.working_dir {
+            cmd.current_dir(working_dir);
+        }
+
+        cmd
+    }
+}
 
-impl CommandExecutor for RealCommandExecutor {
+impl CommandExecutor for RealCommandExecutor<'_> {
     fn execute(&self, args: &[String]) -> Result<()> {
-        let mut cmd = if cfg!(target_os = "windows") {
-            let mut cmd = Command::new("cmd");
-            cmd.arg("/C");
-            cmd
-        } else {
-            let mut cmd = Command::new("sh");
-            cmd.arg("-c");
-            cmd
-        };
+        let mut cmd = self.create_command();
 
         log::debug!("Executing '{}'", args.join(" "));
         log::debug!(
             "Working dir '{}'",
-            env::current_dir()
-                .unwrap_or_else(|_| PathBuf::from("."))
+            self.working_dir
+                .map_or_else(
+                    || env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+                    Path::to_path_buf
+                )
                 .display()
         );
 
@@ -62,18 +90,60 @@ impl CommandExecutor for RealCommandExecutor {
             let msg = format!(
                 "Failed to generate PlantUML diagrams, PlantUML exited with code {} ({}).",
                 output.status.code().unwrap_or(-9999),
-                String::from_utf8(output.stderr).unwrap_or_default()
+                String::from_utf8(output.stderr).unwrap_or_default().trim()
             );
             bail!(msg);
         }
 
         Ok(())
     }
+
+    fn output(&self, args: &[String]) -> Result<String> {
+        log::debug!("Executing '{}'", args.join(" "));
+        let output = match self.create_command().arg(args.join(" ")).output() {
+            Ok(output) => output,
+            Err(e) => bail!("Failed to start PlantUML application ({}).", e),
+        };
+
+        // Some PlantUML commands (e.g. -syntax) report errors on stdout with a
+        // non zero exit code
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        if !output.status.success() && stdout.trim().is_empty() {
+            bail!(
+                "PlantUML exited with code {} ({}).",
+                output.status.code().unwrap_or(-9999),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(stdout)
+    }
+}
+
+/// Parse the output of PlantUML's -syntax option. Errors are reported as
+/// `ERROR`, followed by the (zero based) line of the error and the error
+/// messages, valid diagrams report their diagram type instead.
+fn parse_syntax_output(output: &str) -> Option<SyntaxError> {
+    let mut lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+    if lines.next()? != "ERROR" {
+        return None;
+    }
+
+    let line = lines.next().and_then(|l| l.parse().ok());
+    let messages: Vec<&str> = lines.collect();
+    let message = if messages.is_empty() {
+        String::from("Syntax Error?")
+    } else {
+        messages.join(" ")
+    };
+
+    Some(SyntaxError { line, message })
 }
 
 pub struct PlantUMLShell {
     plantuml_cmd: String,
     generation_dir: TempDir,
+    working_dir: Option<PathBuf>,
 }
 
 /// Invokes PlantUML as a shell/cmd program.
@@ -82,6 +152,21 @@ impl PlantUMLShell {
         Self {
             plantuml_cmd,
             generation_dir: tempdir().unwrap(),
+            working_dir: None,
+        }
+    }
+
+    /// Run PlantUML in `working_dir` instead of the current dir (mdbook runs
+    /// the preprocessor in the book's root dir, relative paths in the PlantUML
+    /// command are relative to it)
+    pub fn with_working_dir(mut self, working_dir: &Path) -> Self {
+        self.working_dir = Some(working_dir.to_path_buf());
+        self
+    }
+
+    fn create_executor(&self) -> RealCommandExecutor<'_> {
+        RealCommandExecutor {
+            working_dir: self.working_dir.as_deref(),
         }
     }
 
@@ -101,6 +186,22 @@ impl PlantUMLShell {
         Ok(args)
     }
 
+    /// Get the command line for generating the client side image map of the
+    /// given source entry (PlantUML writes the map to stdout).
+    fn get_image_map_cmd_arguments(&self, file: &Path, map_file: &Path) -> Result<Vec<String>> {
+        match (file.to_str(), map_file.to_str()) {
+            (Some(src), Some(dst)) => Ok(vec![
+                self.plantuml_cmd.clone(),
+                String::from("-pipemap"),
+                String::from("<"),
+                String::from(src),
+                String::from(">"),
+                String::from(dst),
+            ]),
+            _ => bail!("Failed to stringify temporary PlantUML file path."),
+        }
+    }
+
     /// Create the source and image names for the generation dir with the
     /// appropriate extensions
     fn get_filenames(&self, output_file: &Path) -> (PathBuf, PathBuf) {
@@ -119,6 +220,33 @@ impl PlantUMLShell {
         (puml_src, puml_image)
     }
 
+    /// Check the syntax of the given plantuml code using PlantUML's -syntax
+    /// option (which reads the diagram from stdin).
+    fn check_syntax(
+        &self,
+        plantuml_code: &str,
+        command_executor: &dyn CommandExecutor,
+    ) -> Result<Option<SyntaxError>> {
+        let src_file = self.generation_dir.path().join("syntax-check.puml");
+        if let Err(e) = fs::write(&src_file, plantuml_code) {
+            bail!("Failed to create temp file for inline diagram ({}).", e);
+        }
+        let src = match src_file.to_str() {
+            Some(src) => String::from(src),
+            None => bail!("Failed to stringify temporary PlantUML file path."),
+        };
+
+        let args = vec![
+            self.plantuml_cmd.clone(),
+            String::from("-syntax"),
+            String::from("<"),
+            src,
+        ];
+        let output = command_executor.output(&args)?;
+
+        Ok(parse_syntax_output(&output))
+    }
+
     /// Generate an image file from the given plantuml code.
     fn render_from_string(
         &self,
@@ -136,7 +264,11 @@ impl PlantUMLShell {
 
         // Render the diagram, PlantUML will create a file with the same base
         // name, and the image extension
-        let args = self.get_cmd_arguments(&puml_src, image_format)?;
+        let args = if image_format == IMAGE_MAP_FORMAT {
+            self.get_image_map_cmd_arguments(&puml_src, &puml_image)?
+        } else {
+            self.get_cmd_arguments(&puml_src, image_format)?
+        };
         command_executor.execute(&args).or_else(|e| {
             bail!("Failed to render inline diagram ({}).", e);
         })?;
@@ -149,13 +281,18 @@ impl PlantUMLShell {
             ));
         }
 
-        if let Err(e) = fs::copy(&puml_image, &output_file) {
-            bail!(
-                "Error copying the generated PlantUML image {} from to {} ({}).",
-                puml_image.to_string_lossy(),
-                output_file.to_string_lossy(),
-                e
-            );
+        // Copy all pages, PlantUML generates foo.svg, foo_001.svg, ... for multi
+        // page diagrams
+        for (page, page_image) in get_existing_pages(&puml_image).iter().enumerate() {
+            let page_output_file = get_page_filename(output_file, page);
+            if let Err(e) = fs::copy(page_image, &page_output_file) {
+                bail!(
+                    "Error copying the generated PlantUML image {} from to {} ({}).",
+                    page_image.to_string_lossy(),
+                    page_output_file.to_string_lossy(),
+                    e
+                );
+            }
         }
 
         Ok(())
@@ -169,9 +306,14 @@ impl PlantUMLBackend for PlantUMLShell {
         image_format: &str,
         output_file: &Path,
     ) -> Result<()> {
-        let executor = RealCommandExecutor {};
+        let executor = self.create_executor();
         Self::render_from_string(self, plantuml_code, image_format, output_file, &executor)
     }
+
+    fn check_syntax(&self, plantuml_code: &str) -> Result<Option<SyntaxError>> {
+        let executor = self.create_executor();
+        Self::check_syntax(self, plantuml_code, &executor)
+    }
 }
 
 #[cfg(test)]
@@ -185,6 +327,7 @@ mod tests {
     struct FakeCommandExecutor {
         error: bool,
         create_file: bool,
+        pages: usize,
     }
 
     impl CommandExecutor for FakeCommandExecutor {
@@ -200,10 +343,100 @@ mod tests {
                     // Simply copy the contents of source to the output file
                     filename.set_extension("svg");
                     fs::write(filename.as_path(), &source)?;
+                    for page in 1..self.pages {
+                        let page_file = get_page_filename(&filename, page);
+                        fs::write(page_file, format!("page {}", page))?;
+                    }
                 }
                 Ok(())
             }
         }
+
+        fn output(&self, args: &[String]) -> Result<String> {
+            if self.error {
+                bail!("Whoops")
+            }
+
+            // Last argument is the redirected source file
+            let source = fs::read_to_string(args.last().unwrap())?;
+            if source.contains("-->>") {
+                Ok(String::from("ERROR\n1\nSyntax Error?\n"))
+            } else {
+                Ok(String::from("CLASS\n(2 entities)\n"))
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_syntax_output() {
+        assert_eq!(None, parse_syntax_output("SEQUENCE\n(2 participants)\n"));
+        assert_eq!(None, parse_syntax_output(""));
+        assert_eq!(
+            Some(SyntaxError {
+                line: Some(3),
+                message: String::from("Syntax Error? Some diagram description contains errors"),
+            }),
+            parse_syntax_output(
+                "ERROR\n3\nSyntax Error?\nSome diagram description contains errors\n"
+            )
+        );
+        assert_eq!(
+            Some(SyntaxError {
+                line: None,
+                message: String::from("Syntax Error?"),
+            }),
+            parse_syntax_output("ERROR\n")
+        );
+    }
+
+    #[test]
+    fn checks_syntax_using_stdin() {
+        let shell = PlantUMLShell::new(String::from("plantumlcmd"));
+        let executor = FakeCommandExecutor {
+            error: false,
+            create_file: false,
+            pages: 1,
+        };
+        assert_eq!(
+            None,
+            shell
+                .check_syntax("@startuml\nA --> B\n@enduml\n", &executor)
+                .unwrap()
+        );
+        assert_eq!(
+            Some(1),
+            shell
+                .check_syntax("@startuml\nA -->> B\n@enduml\n", &executor)
+                .unwrap()
+                .unwrap()
+                .line
+        );
+
+        let executor = FakeCommandExecutor {
+            error: true,
+            create_file: false,
+            pages: 1,
+        };
+        assert!(shell
+            .check_syntax("@startuml\n@enduml\n", &executor)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn runs_commands_in_the_working_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("plantuml.jar"), "froboz").unwrap();
+
+        let executor = RealCommandExecutor {
+            working_dir: Some(dir.path()),
+        };
+        assert_eq!(
+            "froboz",
+            executor
+                .output(&[String::from("cat"), String::from("plantuml.jar")])
+                .unwrap()
+        );
     }
 
     #[test]
@@ -211,6 +444,7 @@ mod tests {
         let shell = PlantUMLShell {
             plantuml_cmd: String::from("plantumlcmd"),
             generation_dir: tempdir().unwrap(),
+            working_dir: None,
         };
         let file = PathBuf::from("froboz.puml");
         assert_eq!(
@@ -226,6 +460,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn shell_image_map_command_line_arguments() {
+        let shell = PlantUMLShell {
+            plantuml_cmd: String::from("plantumlcmd"),
+            generation_dir: tempdir().unwrap(),
+            working_dir: None,
+        };
+        assert_eq!(
+            vec![
+                String::from("plantumlcmd"),
+                String::from("-pipemap"),
+                String::from("<"),
+                String::from("froboz.puml"),
+                String::from(">"),
+                String::from("froboz.cmapx")
+            ],
+            shell
+                .get_image_map_cmd_arguments(Path::new("froboz.puml"), Path::new("froboz.cmapx"))
+                .unwrap()
+        );
+    }
+
     fn run_render_from_string(
         generate_error: bool,
         create_file: bool,
@@ -240,11 +496,13 @@ mod tests {
         let shell = PlantUMLShell {
             plantuml_cmd: String::default(),
             generation_dir: output_dir,
+            working_dir: None,
         };
 
         let executor = FakeCommandExecutor {
             error: generate_error,
             create_file,
+            pages: 1,
         };
 
         shell.render_from_string(
@@ -296,6 +554,37 @@ mod tests {
         };
     }
 
+    #[test]
+    fn copies_all_pages_of_multi_page_diagrams() {
+        let img_dir = tempdir().unwrap();
+        let output_file = join_path(img_dir.path(), "foobar.svg");
+
+        let shell = PlantUMLShell {
+            plantuml_cmd: String::default(),
+            generation_dir: tempdir().unwrap(),
+            working_dir: None,
+        };
+
+        let executor = FakeCommandExecutor {
+            error: false,
+            create_file: true,
+            pages: 3,
+        };
+
+        shell
+            .render_from_string("page 0", "svg", &output_file, &executor)
+            .unwrap();
+
+        for page in 0..3 {
+            let raw_source = fs::read(get_page_filename(&output_file, page)).unwrap();
+            assert_eq!(
+                format!("page {}", page),
+                String::from_utf8_lossy(&raw_source)
+            );
+        }
+        assert!(!get_page_filename(&output_file, 3).exists());
+    }
+
     #[test]
     fn get_filenames_returns_input_and_output_filename() {
         macro_rules! get_names {
@@ -312,6 +601,7 @@ mod tests {
         let shell = PlantUMLShell {
             plantuml_cmd: String::default(),
             generation_dir: tempdir().unwrap(),
+            working_dir: None,
         };
 
         assert_eq!(
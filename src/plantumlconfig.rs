@@ -1,5 +1,14 @@
+use crate::figures::FigureNumbering;
 use serde::{Deserialize, Serialize};
 
+/// How readers can zoom in on diagrams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZoomMode {
+    /// Open the diagram in an in-page overlay with pan and zoom
+    Lightbox,
+}
+
 /// The configuration options available with this backend.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default, rename_all = "kebab-case")]
@@ -12,6 +21,69 @@ pub struct PlantUMLConfig {
     /// This is convenient for large diagrams which are hard to see in the book.
     /// The default value is `false`.
     pub clickable_img: bool,
+    /// Render png diagrams at both normal and double resolution and let the
+    /// browser pick the right one (using the img srcset attribute), so png
+    /// diagrams look sharp on HiDPI screens.
+    /// The default value is `false`.
+    pub hidpi_png: bool,
+    /// PlantUML theme (see `!theme`) used for rendering a dark variant of
+    /// every diagram. The dark variant is shown instead of the regular diagram
+    /// when a dark mdbook theme (coal, navy or ayu) is active.
+    /// By default no dark variant is rendered.
+    pub dark_theme: Option<String>,
+    /// PlantUML source (e.g. skinparams) added to every diagram for rendering
+    /// the dark variant (added after the `dark-theme`, if any).
+    /// By default no dark variant is rendered.
+    pub dark_preamble: Option<String>,
+    /// Add a collapsible block containing the PlantUML source below every
+    /// diagram (with a link to the PlantUML server's editor when a server is
+    /// used for rendering).
+    /// The default value is `false`.
+    pub show_source: bool,
+    /// How diagrams with an id are numbered as figures, either per `chapter`
+    /// (e.g. "Figure 3.2") or across the whole `book` (e.g. "Figure 7").
+    /// The default value is `chapter`.
+    pub figure_numbering: FigureNumbering,
+    /// Title of a "List of Figures" chapter added to the end of the book,
+    /// listing all the figures. The list can also be added to any chapter
+    /// using the `{{#plantuml-figures}}` placeholder.
+    /// By default no chapter is added.
+    pub list_of_figures: Option<String>,
+    /// Add the text labels of every diagram in a visually hidden element next
+    /// to the image, so the diagram text is found by mdbook's search (and read
+    /// by screen readers).
+    /// The default value is `false`.
+    pub searchable_text: bool,
+    /// Replace the fixed width and height of svg images with a `max-width` of
+    /// 100%, so large diagrams scale down to the content width.
+    /// The default value is `false`.
+    pub svg_responsive: bool,
+    /// Strip comments, processing instructions and metadata (which includes
+    /// the diagram source) from svg images.
+    /// The default value is `false`.
+    pub svg_strip_comments: bool,
+    /// Remove the formatting whitespace from svg images.
+    /// The default value is `false`.
+    pub svg_minify: bool,
+    /// Zoom mode for the diagrams, `lightbox` opens a clicked diagram in an
+    /// in-page overlay with pan and zoom (this takes precedence over
+    /// `clickable-img`).
+    /// By default diagrams cannot be zoomed.
+    pub zoom: Option<ZoomMode>,
+    /// Insert the images with `loading="lazy"` and their intrinsic size, so
+    /// browsers defer loading offscreen diagrams and can reserve their space.
+    /// The default value is `false`.
+    pub lazy_loading: bool,
+    /// The log level (`off`, `error`, `warn`, `info`, `debug` or `trace`).
+    /// The `--log-level` command line option and the `MDBOOK_LOG` and
+    /// `RUST_LOG` environment variables take precedence.
+    /// The default is `warn`.
+    pub log_level: Option<String>,
+    /// Path of a JSON report written after each build (relative to the book
+    /// root), with per diagram render statistics (cache hits, render times,
+    /// image sizes and errors).
+    /// By default no report is written.
+    pub build_report: Option<String>,
 }
 
 #[cfg(test)]
@@ -23,5 +95,20 @@ mod tests {
     fn default() {
         let cfg = PlantUMLConfig::default();
         assert_eq!(cfg.plantuml_cmd, None);
+        assert!(!cfg.clickable_img);
+        assert!(!cfg.hidpi_png);
+        assert_eq!(cfg.dark_theme, None);
+        assert_eq!(cfg.dark_preamble, None);
+        assert!(!cfg.show_source);
+        assert_eq!(cfg.figure_numbering, FigureNumbering::Chapter);
+        assert_eq!(cfg.list_of_figures, None);
+        assert!(!cfg.searchable_text);
+        assert!(!cfg.svg_responsive);
+        assert!(!cfg.svg_strip_comments);
+        assert!(!cfg.svg_minify);
+        assert_eq!(cfg.zoom, None);
+        assert!(!cfg.lazy_loading);
+        assert_eq!(cfg.log_level, None);
+        assert_eq!(cfg.build_report, None);
     }
 }
@@ -0,0 +1,142 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The length of the progress line currently shown on stderr, 0 if none
+static STDERR_LINE_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Clear the progress line on stderr (if shown), so a log record written to
+/// stderr does not end up on the same line. The next progress update shows
+/// the line again.
+pub fn clear_progress_line() {
+    let line_len = STDERR_LINE_LEN.swap(0, Ordering::Relaxed);
+    let _ = clear_line(&mut io::stderr(), line_len);
+}
+
+fn clear_line(output: &mut impl Write, line_len: usize) -> io::Result<()> {
+    if line_len > 0 {
+        write!(output, "\r{}\r", " ".repeat(line_len))?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Progress indicator for rendering the diagrams of a book, shown on a single
+/// (overwritten) line of `output` (stderr by default)
+pub struct Progress<W: Write = io::Stderr> {
+    total: usize,
+    rendered: usize,
+    cached: usize,
+    output: W,
+    /// True if `output` is stderr, which is shared with the log output
+    is_stderr: bool,
+}
+
+impl Progress {
+    /// Create a progress indicator for rendering `total` diagrams, or None
+    /// when stderr is not a terminal (e.g. when the build output is logged)
+    pub fn for_stderr(total: usize) -> Option<Self> {
+        if total == 0 || !atty::is(atty::Stream::Stderr) {
+            return None;
+        }
+
+        let mut progress = Self::new(total, io::stderr());
+        progress.is_stderr = true;
+        Some(progress)
+    }
+}
+
+impl<W: Write> Progress<W> {
+    fn new(total: usize, output: W) -> Self {
+        Self {
+            total,
+            rendered: 0,
+            cached: 0,
+            output,
+            is_stderr: false,
+        }
+    }
+
+    /// Count a rendered diagram and update the progress line
+    pub fn advance(&mut self, cached: bool) {
+        self.rendered += 1;
+        if cached {
+            self.cached += 1;
+        }
+
+        let message = format_message(self.rendered, self.total, self.cached);
+        let _ = write!(self.output, "\r{}", message);
+        let line_len = if self.rendered == self.total {
+            let _ = writeln!(self.output);
+            0
+        } else {
+            message.len()
+        };
+        let _ = self.output.flush();
+        if self.is_stderr {
+            STDERR_LINE_LEN.store(line_len, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<W: Write> Drop for Progress<W> {
+    fn drop(&mut self) {
+        // Don't leave the cursor on the progress line when rendering stopped
+        // early
+        if self.rendered > 0 && self.rendered < self.total {
+            let _ = writeln!(self.output);
+        }
+        if self.is_stderr {
+            STDERR_LINE_LEN.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+fn format_message(rendered: usize, total: usize, cached: usize) -> String {
+    format!(
+        "rendering {}/{} diagrams ({} cached)",
+        rendered, total, cached
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_format_message() {
+        assert_eq!(
+            "rendering 37/412 diagrams (12 cached)",
+            format_message(37, 412, 12)
+        );
+    }
+
+    #[test]
+    fn test_clear_line() {
+        let mut output = Vec::new();
+        clear_line(&mut output, 5).unwrap();
+        assert_eq!("\r     \r", String::from_utf8_lossy(&output));
+
+        let mut output = Vec::new();
+        clear_line(&mut output, 0).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_advance() {
+        let mut progress = Progress::new(2, Vec::new());
+        progress.advance(true);
+        assert_eq!(
+            "\rrendering 1/2 diagrams (1 cached)",
+            String::from_utf8_lossy(&progress.output)
+        );
+
+        // The line is ended when done
+        progress.advance(false);
+        assert_eq!(
+            "\rrendering 1/2 diagrams (1 cached)\rrendering 2/2 diagrams (1 cached)\n",
+            String::from_utf8_lossy(&progress.output)
+        );
+    }
+}
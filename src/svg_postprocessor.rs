@@ -0,0 +1,249 @@
+use crate::plantumlconfig::PlantUMLConfig;
+
+/// Post-process a PlantUML generated SVG image according to the svg-*
+/// configuration options. Returns None when no post-processing is enabled.
+pub fn postprocess_svg(svg: &str, cfg: &PlantUMLConfig) -> Option<String> {
+    if !(cfg.svg_responsive || cfg.svg_strip_comments || cfg.svg_minify) {
+        return None;
+    }
+
+    let mut svg = String::from(svg);
+    if cfg.svg_strip_comments {
+        svg = strip_comments(&svg);
+    }
+    if cfg.svg_responsive {
+        svg = make_responsive(&svg);
+    }
+    if cfg.svg_minify {
+        svg = minify(&svg);
+    }
+
+    Some(svg)
+}
+
+/// Replace the fixed width and height of the svg element with a `max-width`
+/// of 100% (keeping the `viewBox`), so the image scales down to the content
+/// width.
+fn make_responsive(svg: &str) -> String {
+    let (tag_start, tag_end) = match find_svg_tag(svg) {
+        Some(tag) => tag,
+        None => return String::from(svg),
+    };
+    let tag = &svg[tag_start..tag_end];
+
+    let width = get_attribute(tag, "width");
+    let height = get_attribute(tag, "height");
+    let mut new_tag = remove_attribute(tag, "width");
+    new_tag = remove_attribute(&new_tag, "height");
+    // Scale uniformly instead of stretching
+    new_tag = remove_attribute(&new_tag, "preserveAspectRatio");
+
+    if get_attribute(&new_tag, "viewBox").is_none() {
+        let size = width.zip(height).and_then(|(width, height)| {
+            let width = width.trim_end_matches("px").parse::<f64>().ok()?;
+            let height = height.trim_end_matches("px").parse::<f64>().ok()?;
+            Some((width, height))
+        });
+        if let Some((width, height)) = size {
+            new_tag = add_attribute(&new_tag, "viewBox", &format!("0 0 {} {}", width, height));
+        }
+    }
+
+    let style: Vec<String> = get_attribute(&new_tag, "style")
+        .unwrap_or_default()
+        .split(';')
+        .map(str::trim)
+        .filter(|declaration| {
+            let property = declaration.split(':').next().unwrap_or_default().trim();
+            !declaration.is_empty() && property != "width" && property != "height"
+        })
+        .map(String::from)
+        .chain(std::iter::once(String::from("max-width:100%")))
+        .collect();
+    new_tag = remove_attribute(&new_tag, "style");
+    new_tag = add_attribute(&new_tag, "style", &format!("{};", style.join(";")));
+
+    format!("{}{}{}", &svg[..tag_start], new_tag, &svg[tag_end..])
+}
+
+/// Remove comments, processing instructions (other than the XML declaration)
+/// and metadata elements
+fn strip_comments(svg: &str) -> String {
+    let mut stripped = remove_between(svg, "<!--", "-->");
+    stripped = remove_between(&stripped, "<metadata", "</metadata>");
+
+    let mut result = String::with_capacity(stripped.len());
+    let mut pos = 0;
+    while let Some(start) = stripped[pos..].find("<?").map(|p| p + pos) {
+        let end = match stripped[start..].find("?>") {
+            Some(end) => start + end + 2,
+            None => break,
+        };
+        result.push_str(&stripped[pos..start]);
+        if stripped[start..].starts_with("<?xml ") {
+            result.push_str(&stripped[start..end]);
+        }
+        pos = end;
+    }
+    result.push_str(&stripped[pos..]);
+
+    result
+}
+
+/// Remove the whitespace between tags that contains a line break (i.e. the
+/// formatting), whitespace in text content is kept.
+fn minify(svg: &str) -> String {
+    let mut minified = String::with_capacity(svg.len());
+    let mut pos = 0;
+    while let Some(tag_end) = svg[pos..].find('>').map(|p| p + pos + 1) {
+        minified.push_str(&svg[pos..tag_end]);
+        let rest = &svg[tag_end..];
+        let whitespace_len = rest.len() - rest.trim_start().len();
+        let whitespace = &rest[..whitespace_len];
+        let next_is_tag = rest[whitespace_len..].starts_with('<');
+        if !(next_is_tag && whitespace.contains('\n')) {
+            minified.push_str(whitespace);
+        }
+        pos = tag_end + whitespace_len;
+    }
+    minified.push_str(&svg[pos..]);
+
+    minified.trim().to_string()
+}
+
+/// Find the byte range of the opening svg tag
+pub fn find_svg_tag(svg: &str) -> Option<(usize, usize)> {
+    let start = svg.find("<svg")?;
+    let end = start + svg[start..].find('>')?;
+    // Keep the / of a self closing tag out of the attributes
+    let end = if svg[..end].ends_with('/') {
+        end - 1
+    } else {
+        end
+    };
+
+    Some((start, end))
+}
+
+/// Get the value of the (double quoted) attribute `name` in `tag`
+pub fn get_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let (value_start, value_end) = find_attribute(tag, name)?.1;
+    Some(&tag[value_start..value_end])
+}
+
+fn remove_attribute(tag: &str, name: &str) -> String {
+    match find_attribute(tag, name) {
+        Some(((start, end), _)) => format!("{}{}", &tag[..start], &tag[end..]),
+        None => String::from(tag),
+    }
+}
+
+fn add_attribute(tag: &str, name: &str, value: &str) -> String {
+    format!("{} {}=\"{}\"", tag, name, value)
+}
+
+/// Find the attribute `name` in `tag`, returns the byte range of the whole
+/// attribute (including the preceding space) and the byte range of its value.
+fn find_attribute(tag: &str, name: &str) -> Option<((usize, usize), (usize, usize))> {
+    let pattern = format!(" {}=\"", name);
+    let start = tag.find(&pattern)?;
+    let value_start = start + pattern.len();
+    let value_end = value_start + tag[value_start..].find('"')?;
+
+    Some(((start, value_end + 1), (value_start, value_end)))
+}
+
+/// Remove everything from `start` up to and including `end`
+fn remove_between(text: &str, start: &str, end: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut pos = 0;
+    while let Some(from) = text[pos..].find(start).map(|p| p + pos) {
+        let to = match text[from..].find(end) {
+            Some(to) => from + to + end.len(),
+            None => break,
+        };
+        result.push_str(&text[pos..from]);
+        pos = to;
+    }
+    result.push_str(&text[pos..]);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SVG: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\
+        <svg xmlns=\"http://www.w3.org/2000/svg\" height=\"120px\" \
+        preserveAspectRatio=\"none\" style=\"width:300px;height:120px;background:#FFFFFF;\" \
+        version=\"1.1\" viewBox=\"0 0 300 120\" width=\"300px\">\n\
+        <defs/>\n\
+        <g>\n\
+        <!--class A-->\n\
+        <text x=\"1\">A  B</text>\n\
+        </g>\n\
+        <?plantuml 1.2022.4?>\n\
+        <metadata><source>A --|> B</source></metadata>\n\
+        </svg>\n";
+
+    #[test]
+    fn disabled_by_default() {
+        assert_eq!(None, postprocess_svg(SVG, &PlantUMLConfig::default()));
+    }
+
+    #[test]
+    fn test_make_responsive() {
+        assert_eq!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" viewBox=\"0 0 300 120\" \
+             style=\"background:#FFFFFF;max-width:100%;\"><g/></svg>",
+            make_responsive(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" height=\"120px\" \
+                 preserveAspectRatio=\"none\" style=\"width:300px;height:120px;background:#FFFFFF;\" \
+                 version=\"1.1\" viewBox=\"0 0 300 120\" width=\"300px\"><g/></svg>"
+            )
+        );
+
+        // The viewBox is added when missing
+        assert_eq!(
+            "<svg viewBox=\"0 0 30 12\" style=\"max-width:100%;\"/>",
+            make_responsive("<svg width=\"30px\" height=\"12\"/>")
+        );
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(
+            "<?xml version=\"1.0\"?><svg><g>\n\n<text>A</text></g>\n</svg>",
+            strip_comments(
+                "<?xml version=\"1.0\"?><svg><g><!--class A-->\n<?plantuml 1.2022.4?>\n\
+                 <text>A</text></g><metadata>x</metadata>\n</svg>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(
+            "<svg><g><text x=\"1\">A  B</text> <text>C</text></g></svg>",
+            minify("<svg>\n  <g>\n    <text x=\"1\">A  B</text> <text>C</text>\n  </g>\n</svg>\n")
+        );
+    }
+
+    #[test]
+    fn test_postprocess_svg() {
+        let cfg = PlantUMLConfig {
+            svg_responsive: true,
+            svg_strip_comments: true,
+            svg_minify: true,
+            ..PlantUMLConfig::default()
+        };
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" viewBox=\"0 0 300 120\" \
+             style=\"background:#FFFFFF;max-width:100%;\"><defs/><g><text x=\"1\">A  B</text></g></svg>",
+            postprocess_svg(SVG, &cfg).unwrap()
+        );
+    }
+}